@@ -0,0 +1,7 @@
+# This script runs the checks CI runs on every commit: a build, clippy with all warnings denied, and the test suite
+# It needs Rust 1.63 or newer (see rust-version in Cargo.toml) and the narm, neutron-common and neutron-star repositories next to this one
+
+set -e
+cargo build --workspace
+cargo clippy --workspace --all-targets -- -D warnings
+cargo test --workspace
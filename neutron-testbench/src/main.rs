@@ -1,10 +1,8 @@
-extern crate elf;
-
 use std::{cell::RefCell, env};
-use std::path::PathBuf;
 use neutron_host::{db::MemoryGlobalState, element_interfaces::logging::StdoutLogger, manager::*};
 use neutron_host::callsystem::*;
 use neutron_host::codata::*;
+use neutron_host::elf_loader::*;
 use neutron_host::interface::*;
 use neutron_host::narm_hypervisor::*;
 use neutron_host::vmmanager::*;
@@ -18,11 +16,13 @@ fn main() {
         println!("Expected smart contract file is an ARM architecture executable compiled as an ELF file");
         return;
     }
-    let path = PathBuf::from(&args[1]);
-    let file = elf::File::open_path(&path).unwrap();
-
-    let text_scn = file.get_section(".text").unwrap();
-    assert!(text_scn.shdr.addr == 0x10000);
+    let image = match load_elf_file(&args[1]){
+        Ok(image) => image,
+        Err(e) => {
+            println!("Unable to load smart contract file: {}", e);
+            return;
+        }
+    };
 
     //setup Neutron
     let mut manager = Manager::default();
@@ -48,14 +48,13 @@ fn main() {
     //Setup execution context
     let mut context = ExecutionContext::default();
     codata.gas_remaining = MAX_GAS;
-    neutron_host::reset_to_random_address(&mut context.self_address);
-    context.self_address.version = 2; //to match NARM VM number
+    //deterministic, so that runs can be reproduced
+    context.self_address = neutron_host::contract_address::derive_contract_address(&context.sender, 0, 2); //version to match NARM VM number
     context.execution_type = ExecutionType::BareExecution;
 
     //Push contract bytecode into Neutron from ELF file
     codata.push_context(context).unwrap();
-    codata.push_input_key("!.c".as_bytes(), &text_scn.data).unwrap();
-    codata.push_input_key("!.d".as_bytes(), &[0]).unwrap();
+    image.push_input_keys(&mut codata).unwrap();
 
     println!("Beginning contract execution");
     let result = manager.execute(&mut codata, &callsystem, &vmm).unwrap();
@@ -3,6 +3,20 @@
 rustfmt ./tests/*.rs
 rustfmt ./tests/contracts/default_env/src/bin/*.rs
 rustfmt ./src/element_interfaces/debug_data.rs
+rustfmt ./src/element_interfaces/call_contract.rs
+rustfmt ./src/element_interfaces/upgrade.rs
+rustfmt ./src/element_interfaces/chain_info.rs
+rustfmt ./src/element_interfaces/coins.rs
+rustfmt ./src/element_interfaces/events.rs
+rustfmt ./src/element_interfaces/hashing.rs
+rustfmt ./src/element_interfaces/signatures.rs
+rustfmt ./src/observer.rs
+rustfmt ./src/contract_address.rs
+rustfmt ./src/scheduler.rs
+rustfmt ./src/block_executor.rs
+rustfmt ./src/test_helpers.rs
 rustfmt ./src/narm_hypervisor.rs
+rustfmt ./src/elf_loader.rs
+rustfmt ./src/narm_validator.rs
 rustfmt ./src/comap_abi_decoder.rs
 rustfmt ./src/harness.rs
@@ -0,0 +1,287 @@
+//! Execution of the transactions of a block, producing a receipt for each transaction
+
+use crate::codata::*;
+use crate::element_interfaces::events::{Bloom, Event};
+use crate::element_interfaces::logging::LogMessage;
+use crate::element_interfaces::storage::*;
+use crate::interface::*;
+use crate::neutronerror::*;
+use crate::scheduler::*;
+
+/// A transaction to be executed within a block
+#[derive(Clone, Debug, Default)]
+pub struct BlockTransaction {
+    /// The inputs, outputs and fees of the transaction. This is given to the execution through the ChainInfo element
+    pub transaction: TransactionContext,
+    /// The top level contract execution performed by the transaction. Its gas limit is the gas reserved from the block by the transaction
+    pub execution: ScheduledExecution,
+}
+
+impl BlockTransaction {
+    /// The price of a unit of gas, as the gas fees of the transaction pay for its entire gas limit
+    pub fn gas_price(&self) -> u64 {
+        self.transaction
+            .total_gas_fees
+            .checked_div(self.execution.context.gas_limit)
+            .unwrap_or(0)
+    }
+}
+
+/// The outcome of a transaction executed within a block
+#[derive(Clone, Debug, Default)]
+pub struct TransactionReceipt {
+    pub status: u32,
+    /// If set, the transaction failed and none of its state changes were applied
+    pub reverted: bool,
+    /// The error of a transaction which failed with an unrecoverable error (such as running out of gas) rather than reverting
+    /// Only errors caused by the transaction itself are kept in a receipt, see is_transaction_error
+    pub error: Option<NeutronError>,
+    pub gas_used: u64,
+    /// The gas fees paid for gas which was reserved but not used by the transaction, which are to be refunded by the host
+    pub gas_fees_refunded: u64,
+    /// The total gas used by the block up to and including this transaction
+    pub cumulative_gas_used: u64,
+    pub logs: Vec<LogMessage>,
+    pub events: Vec<Event>,
+    /// Bloom filter of the addresses and topics of the events of the transaction
+    pub bloom: Bloom,
+    pub state_changes: StateWrites,
+}
+
+/// The receipts of all transactions of an executed block
+#[derive(Clone, Debug, Default)]
+pub struct BlockResult {
+    pub receipts: Vec<TransactionReceipt>,
+    pub gas_used: u64,
+    /// Bloom filter combining the blooms of all receipts
+    pub bloom: Bloom,
+}
+
+/// Executes the transactions of a block in order
+/// `execute` runs a single transaction through a Manager, which is responsible for reverting the state changes of failed transactions
+/// The state must have an open checkpoint, which receives the state changes of the block
+/// If a transaction would exceed the block gas limit, the state changes of the entire block are reverted and BlockGasLimitExceeded is returned
+/// Likewise, if a transaction fails with an error which is not caused by the transaction itself, the block is reverted and the error is returned
+pub fn execute_block<S, F>(
+    state: &mut S,
+    block: &BlockContext,
+    transactions: &[BlockTransaction],
+    mut execute: F,
+) -> Result<BlockResult, NeutronError>
+where
+    S: GlobalState,
+    F: FnMut(&mut dyn GlobalState, &BlockContext, &BlockTransaction) -> ExecutionOutcome,
+{
+    //the checkpoint of the block is kept by the host rather than by an execution, so no context is needed
+    let mut codata = CoData::new();
+    state.create_checkpoint(&mut codata)?;
+    let mut result = BlockResult::default();
+    for transaction in transactions.iter() {
+        let gas_limit = transaction.execution.context.gas_limit;
+        if gas_limit > block.gas_limit.saturating_sub(result.gas_used) {
+            state.revert_checkpoint(&mut codata)?;
+            return Err(NeutronError::Unrecoverable(UnrecoverableError::BlockGasLimitExceeded));
+        }
+        let mut receipt = match execute(&mut *state, block, transaction) {
+            Ok(r) => TransactionReceipt {
+                status: r.status,
+                reverted: r.reverted,
+                error: None,
+                gas_used: r.gas_used,
+                gas_fees_refunded: 0,
+                cumulative_gas_used: 0,
+                logs: r.logs,
+                events: r.events,
+                bloom: r.events_bloom,
+                state_changes: r.storage_writes,
+            },
+            Err(e) if is_transaction_error(&e) => TransactionReceipt {
+                reverted: true,
+                error: Some(e),
+                //the gas used can not be known after an unrecoverable error, so the entire gas limit is charged
+                gas_used: gas_limit,
+                ..TransactionReceipt::default()
+            },
+            Err(e) => {
+                state.revert_checkpoint(&mut codata)?;
+                return Err(e);
+            }
+        };
+        receipt.gas_fees_refunded = gas_limit.saturating_sub(receipt.gas_used) * transaction.gas_price();
+        result.gas_used += receipt.gas_used;
+        receipt.cumulative_gas_used = result.gas_used;
+        result.bloom.accrue_bloom(&receipt.bloom);
+        result.receipts.push(receipt);
+    }
+    state.commit_checkpoint(&mut codata)?;
+    Ok(result)
+}
+
+/// Checks if an error returned by a top level execution was caused by the transaction rather than by the host, in which case it
+/// only fails the transaction. These are running out of gas, and accessing state which is out of rent (ie, loading a key which does not
+/// exist). All other errors, including failing to set up the VM and the state being inconsistent, fail the entire block
+pub fn is_transaction_error(error: &NeutronError) -> bool {
+    match error {
+        NeutronError::Unrecoverable(UnrecoverableError::OutOfGas) | NeutronError::Unrecoverable(UnrecoverableError::StateOutOfRent) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callsystem::*;
+    use crate::db::*;
+    use crate::test_helpers::*;
+    use crate::vmmanager::*;
+    use neutron_common::*;
+
+    /// Stores its gas limit and then uses 100 gas. Reverts if input key [30] is set to 1, fails with a database error if it is set to 2,
+    /// and fails as if the VM could not be set up if it is set to 3
+    #[derive(Default)]
+    struct TransactionVM {}
+    impl VMHypervisor for TransactionVM {
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError> {
+            codata.charge_gas(100)?;
+            if codata.peek_input_key(&[30]).unwrap_or(vec![0]) == vec![1] {
+                return Err(NeutronError::Recoverable(RecoverableError::ContractRevertedExecution));
+            }
+            if codata.peek_input_key(&[30]).unwrap_or(vec![0]) == vec![2] {
+                return Err(NeutronError::Unrecoverable(UnrecoverableError::DatabaseWritingError));
+            }
+            if codata.peek_input_key(&[30]).unwrap_or(vec![0]) == vec![3] {
+                return Err(NeutronError::Unrecoverable(UnrecoverableError::ErrorInitializingVM));
+            }
+            Ok(VMResult::Ended(7))
+        }
+        fn set_result(&mut self, _code: u64) {}
+        fn set_error(&mut self, _code: u64) {}
+        fn enter_state(&mut self, codata: &mut CoData, callsystem: &CallSystem) -> Result<(), NeutronError> {
+            let gas_limit = codata.current_context().gas_limit;
+            let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+            storage.store_state(codata, &[1], &gas_limit.to_le_bytes())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            Ok(())
+        }
+    }
+
+    fn execute_transaction(state: &mut dyn GlobalState, _block: &BlockContext, transaction: &BlockTransaction) -> ExecutionOutcome {
+        let transactionvm = || -> Box<dyn VMHypervisor> { Box::from(TransactionVM::default()) };
+        execute_test_vm(state, &transaction.execution, transactionvm)
+    }
+
+    fn transaction(n: u8, gas_limit: u64, revert: bool) -> BlockTransaction {
+        let mut context = ExecutionContext::default();
+        context.self_address = address(n);
+        context.permissions = ContextPermissions::mutable_call();
+        context.gas_limit = gas_limit;
+        let mut execution = ScheduledExecution::new(context);
+        execution.input_map.push((vec![30], vec![revert as u8]));
+        BlockTransaction {
+            execution,
+            ..BlockTransaction::default()
+        }
+    }
+
+    #[test]
+    fn test_block_receipts() {
+        let mut state = MemoryGlobalState::default();
+        state.checkpoint().unwrap();
+        let mut block = BlockContext::default();
+        block.gas_limit = 1000;
+        let mut transactions = vec![transaction(1, 200, false), transaction(2, 200, true), transaction(3, 50, false)];
+        transactions[0].transaction.total_gas_fees = 400;
+        let result = execute_block(&mut state, &block, &transactions, execute_transaction).unwrap();
+        assert_eq!(result.receipts.len(), 3);
+        assert_eq!(result.gas_used, 250);
+
+        let r = &result.receipts[0];
+        assert_eq!((r.status, r.reverted, r.gas_used, r.cumulative_gas_used), (7, false, 100, 100));
+        assert!(r.error.is_none());
+        //gas is paid for at 2 coins per gas, and only half of the gas limit was used
+        assert_eq!(r.gas_fees_refunded, 200);
+        assert_eq!(
+            r.state_changes.get(&address(1)).unwrap().get(&vec![95, 1]).unwrap(),
+            &200u64.to_le_bytes().to_vec()
+        );
+
+        let r = &result.receipts[1];
+        assert_eq!(
+            (r.status, r.reverted, r.gas_used, r.cumulative_gas_used),
+            (RecoverableError::ContractRevertedExecution as u32, true, 100, 200)
+        );
+        assert!(r.state_changes.is_empty());
+
+        let r = &result.receipts[2];
+        assert_eq!((r.reverted, r.gas_used, r.cumulative_gas_used), (true, 50, 250));
+        assert_eq!(r.error, Some(NeutronError::Unrecoverable(UnrecoverableError::OutOfGas)));
+
+        assert!(state.read_key(&address(1), &[95, 1]).is_ok());
+        assert!(state.read_key(&address(2), &[95, 1]).is_err());
+        assert!(state.read_key(&address(3), &[95, 1]).is_err());
+    }
+
+    #[test]
+    fn test_block_gas_limit() {
+        let mut state = MemoryGlobalState::default();
+        state.checkpoint().unwrap();
+        let mut block = BlockContext::default();
+        block.gas_limit = 250;
+        //the entire gas limit of a transaction must fit in the block, so the second transaction does not fit even though it would only use 100 gas
+        let transactions = vec![transaction(1, 100, false), transaction(2, 200, false)];
+        assert_eq!(
+            execute_block(&mut state, &block, &transactions, execute_transaction).unwrap_err(),
+            NeutronError::Unrecoverable(UnrecoverableError::BlockGasLimitExceeded)
+        );
+        //the first transaction fitted in the block, but it is reverted along with the rest of the block
+        assert!(state.read_key(&address(1), &[95, 1]).is_err());
+
+        block.gas_limit = 300;
+        let result = execute_block(&mut state, &block, &transactions, execute_transaction).unwrap();
+        assert_eq!(result.gas_used, 200);
+        assert!(state.read_key(&address(2), &[95, 1]).is_ok());
+    }
+
+    #[test]
+    fn test_block_host_errors() {
+        let mut block = BlockContext::default();
+        block.gas_limit = 1000;
+        let errors = vec![
+            (2, UnrecoverableError::DatabaseWritingError),
+            (3, UnrecoverableError::ErrorInitializingVM),
+        ];
+        for (input, error) in errors {
+            let mut state = MemoryGlobalState::default();
+            state.checkpoint().unwrap();
+            let mut failing = transaction(2, 200, false);
+            failing.execution.input_map[0].1 = vec![input];
+            let transactions = vec![transaction(1, 200, false), failing];
+            //errors which are not caused by the transaction fail the entire block rather than producing a receipt
+            assert_eq!(
+                execute_block(&mut state, &block, &transactions, execute_transaction).unwrap_err(),
+                NeutronError::Unrecoverable(error)
+            );
+            assert!(state.read_key(&address(1), &[95, 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_transaction_errors() {
+        assert!(is_transaction_error(&NeutronError::Unrecoverable(UnrecoverableError::OutOfGas)));
+        assert!(is_transaction_error(&NeutronError::Unrecoverable(
+            UnrecoverableError::StateOutOfRent
+        )));
+        assert!(!is_transaction_error(&NeutronError::Unrecoverable(
+            UnrecoverableError::ErrorInitializingVM
+        )));
+        assert!(!is_transaction_error(&NeutronError::Unrecoverable(
+            UnrecoverableError::DatabaseWritingError
+        )));
+        assert!(!is_transaction_error(&NeutronError::Unrecoverable(
+            UnrecoverableError::DeveloperError
+        )));
+        //recoverable errors of a top level execution are given as a reverted result, so they can only come from the host
+        assert!(!is_transaction_error(&NeutronError::Recoverable(RecoverableError::ItemDoesntExist)));
+    }
+}
@@ -40,9 +40,19 @@ impl <'a>CallSystem<'a>{
         Ok(())
     }
     
+    /// Calls a public element function, charging gas according to the gas schedule in the CoData
+    /// The input costack and comap are charged before the call, and the output costack is charged after it
     pub fn call(&self, codata: &mut CoData, element: u32, function: u32) -> Result<ElementResult, NeutronError>{
         let function = function & (!0x8000_0000); //public calls can not set the top bit, which is reserved for private functions
-        self.private_call(codata, element, function)
+        let schedule = &codata.gas_schedule;
+        let cost = schedule.element_cost(element, function, ELEMENT_COST_BASE)
+            .saturating_add(schedule.element_cost(element, function, ELEMENT_COST_INPUT_COSTACK_BYTE).saturating_mul(codata.input_stack_size()))
+            .saturating_add(schedule.element_cost(element, function, ELEMENT_COST_INPUT_COMAP_BYTE).saturating_mul(codata.input_map_size()));
+        codata.charge_gas(cost)?;
+        let result = self.private_call(codata, element, function)?;
+        let cost = codata.gas_schedule.element_cost(element, function, ELEMENT_COST_OUTPUT_COSTACK_BYTE).saturating_mul(codata.output_stack_size());
+        codata.charge_gas(cost)?;
+        Ok(result)
     }
     pub fn private_call(&self, manager: &mut CoData, element: u32, function: u32) -> Result<ElementResult, NeutronError>{
         match element{
@@ -172,5 +182,54 @@ mod tests {
         let mut codata = CoData::default();
         cs.call(&mut codata, 15, 0).unwrap();
     }
+
+    #[derive(Default)]
+    struct TestElementOutput{
+    }
+    impl ElementAPI for TestElementOutput{
+        fn system_call(&mut self, _callsystem: & CallSystem, codata: &mut CoData, _feature: u32, _function: u32) -> Result<ElementResult, NeutronError>{
+            codata.push_output_stack(&[1, 2, 3, 4])?;
+            Ok(ElementResult::Result(0))
+        }
+    }
+    fn build_schedule() -> GasSchedule{
+        let mut schedule = GasSchedule::default();
+        let mut functions = HashMap::new();
+        functions.insert(1, vec![100, 10, 2, 5]);
+        schedule.element_costs.insert(11, functions);
+        schedule
+    }
+    #[test]
+    fn test_call_charges_gas(){
+        let mut t1 = TestElementOutput::default();
+        let mut cs = CallSystem::default();
+        cs.add_call(11, &mut t1).unwrap();
+        let mut codata = CoData::default();
+        codata.gas_schedule = build_schedule();
+        codata.gas_remaining = 1000;
+        codata.push_output_stack(&[0, 0, 0]).unwrap();
+        codata.push_input_key(&[1, 1], &[2, 2, 2]).unwrap();
+        codata.flip_stacks();
+        //base 100, 3 input costack bytes at 10, 5 input comap bytes at 2, 4 output costack bytes at 5
+        cs.call(&mut codata, 11, 1).unwrap();
+        assert_eq!(codata.gas_remaining, 1000 - 100 - 30 - 10 - 20);
+        //functions missing from the schedule are free
+        cs.call(&mut codata, 11, 0).unwrap();
+        assert_eq!(codata.gas_remaining, 1000 - 160);
+    }
+    #[test]
+    fn test_call_out_of_gas(){
+        let mut t1 = TestElementOutput::default();
+        let mut cs = CallSystem::default();
+        cs.add_call(11, &mut t1).unwrap();
+        let mut codata = CoData::default();
+        codata.gas_schedule = build_schedule();
+        codata.gas_remaining = 50;
+        match cs.call(&mut codata, 11, 1){
+            Err(NeutronError::Unrecoverable(UnrecoverableError::OutOfGas)) => {},
+            _ => panic!("expected OutOfGas")
+        }
+        assert_eq!(codata.gas_remaining, 0);
+    }
 }
 
@@ -5,8 +5,34 @@ use crate::neutronerror::NeutronError::*;
 use std::collections::HashMap;
 use std::convert::*;
 use std::mem;
+use crate::element_interfaces::logging::LogMessage;
+use crate::element_interfaces::events::Event;
+use crate::element_interfaces::hashing::{HASHING_FEATURE, default_hashing_costs};
+
+/// Element cost parameter: flat cost charged for each call of the element function
+pub const ELEMENT_COST_BASE: usize = 0;
+/// Element cost parameter: cost per byte of the costack given as input to the element function
+pub const ELEMENT_COST_INPUT_COSTACK_BYTE: usize = 1;
+/// Element cost parameter: cost per byte (keys and values) of the comap given as input to the element function
+pub const ELEMENT_COST_INPUT_COMAP_BYTE: usize = 2;
+/// Element cost parameter: cost per byte of the costack output by the element function, charged after the call completes
+pub const ELEMENT_COST_OUTPUT_COSTACK_BYTE: usize = 3;
+
+/// The default maximum total size in bytes of the items on the output costack
+pub const DEFAULT_MAX_COSTACK_SIZE: u64 = 1024 * 1024;
+
+/// VM cost: cost per byte of writeable memory allocated by VMs
+pub const VM_COST_WRITEABLE_MEMORY_BYTE: u32 = 1;
+/// VM cost: memory additionally costs the square of the allocated bytes past VM_COST_MEMORY_QUADRATIC_THRESHOLD divided by this value.
+/// 0 disables the quadratic cost
+pub const VM_COST_MEMORY_QUADRATIC_DIVISOR: u32 = 2;
+/// VM cost: cost per byte of read-only memory (ie, contract code) mapped by VMs
+pub const VM_COST_READ_ONLY_MEMORY_BYTE: u32 = 3;
+/// VM cost: the total bytes of writeable and read-only memory which can be allocated before the quadratic cost applies
+pub const VM_COST_MEMORY_QUADRATIC_THRESHOLD: u32 = 4;
+/// VM cost: cost per byte of code and data of a contract being deployed, covering its validation and storage
+pub const VM_COST_DEPLOY_BYTE: u32 = 5;
 
-#[derive(Default)]
 pub struct GasSchedule{
     //vm_operation -> gas cost
     pub vm_costs: HashMap<u32, u64>,
@@ -14,6 +40,43 @@ pub struct GasSchedule{
     pub element_costs: HashMap<u32, HashMap<u32, Vec<u64>>>
 }
 
+impl Default for GasSchedule{
+    /// Costs nothing, except for hashing. Hashing is far cheaper than within a VM, but must still be charged per byte hashed
+    fn default() -> GasSchedule{
+        let mut element_costs = HashMap::new();
+        element_costs.insert(HASHING_FEATURE, default_hashing_costs());
+        GasSchedule{
+            vm_costs: HashMap::new(),
+            element_costs: element_costs
+        }
+    }
+}
+
+impl GasSchedule{
+    /// Gets a cost parameter of an element function. Functions or parameters missing from the schedule cost nothing
+    pub fn element_cost(&self, element: u32, function: u32, parameter: usize) -> u64{
+        match self.element_costs.get(&element).and_then(|f| f.get(&function)){
+            Some(v) => *v.get(parameter).unwrap_or(&0),
+            None => 0
+        }
+    }
+    /// Gets the cost of a VM operation. Operations missing from the schedule cost nothing
+    pub fn vm_cost(&self, operation: u32) -> u64{
+        *self.vm_costs.get(&operation).unwrap_or(&0)
+    }
+    /// The total cost of having the given number of bytes of writeable and read-only memory allocated
+    pub fn memory_cost(&self, writeable: u64, read_only: u64) -> u64{
+        let linear = self.vm_cost(VM_COST_WRITEABLE_MEMORY_BYTE).saturating_mul(writeable)
+            .saturating_add(self.vm_cost(VM_COST_READ_ONLY_MEMORY_BYTE).saturating_mul(read_only));
+        let excess = writeable.saturating_add(read_only).saturating_sub(self.vm_cost(VM_COST_MEMORY_QUADRATIC_THRESHOLD));
+        let quadratic = match self.vm_cost(VM_COST_MEMORY_QUADRATIC_DIVISOR){
+            0 => 0,
+            divisor => excess.saturating_mul(excess) / divisor
+        };
+        linear.saturating_add(quadratic)
+    }
+}
+
 pub struct CoData{
     context_stack: Vec<ExecutionContext>,
     stacks: [Vec<Vec<u8>>; 2],
@@ -29,6 +92,12 @@ pub struct CoData{
     pub vm_writeable_memory: u32,
     pub vm_read_only_memory: u32,
     pub gas_schedule: GasSchedule,
+    /// The maximum total size in bytes of the items on the output costack. Pushing beyond it fails with CostackFull
+    pub max_costack_size: u64,
+    /// Log messages emitted during the execution
+    pub logs: Vec<LogMessage>,
+    /// Events emitted during the execution. Events of reverted contexts are discarded by the Manager
+    pub events: Vec<Event>,
 
     /// Used for certain internal operations, such as loading bytecode, 
     /// where a "pure" call should be allowed to ignore otherwise restrictive permissions for special and determined-safe purposes
@@ -42,6 +111,9 @@ impl Default for CoData{
             vm_writeable_memory: 0,
             vm_read_only_memory: 0,
             gas_schedule: GasSchedule::default(),
+            max_costack_size: DEFAULT_MAX_COSTACK_SIZE,
+            logs: vec![],
+            events: vec![],
             ignore_permissions: false,
             context_stack: vec![],
             stacks: [vec![], vec![]],
@@ -69,7 +141,80 @@ impl CoData{
             self.current_context().permissions
         }
     }
+    /// Runs `f` with permissions ignored, restoring the previous setting afterwards even if `f` fails
+    /// This is meant for host bookkeeping, such as loading bytecode, and should never run code of a smart contract
+    pub fn with_ignored_permissions<T, F: FnOnce(&mut CoData) -> T>(&mut self, f: F) -> T{
+        let ignore_permissions = self.ignore_permissions;
+        self.ignore_permissions = true;
+        let result = f(self);
+        self.ignore_permissions = ignore_permissions;
+        result
+    }
+    /// Consumes the given amount of gas. If not enough gas is left, all remaining gas is consumed and OutOfGas is returned
+    pub fn charge_gas(&mut self, cost: u64) -> Result<(), NeutronError>{
+        if cost > self.gas_remaining{
+            self.gas_remaining = 0;
+            return Err(Unrecoverable(UnrecoverableError::OutOfGas));
+        }
+        self.gas_remaining -= cost;
+        Ok(())
+    }
+    /// Charges the increase of the memory cost of all VMs in the execution, then accounts for the newly allocated memory
+    fn allocate_memory(&mut self, writeable: u32, read_only: u32) -> Result<(), NeutronError>{
+        let new_writeable = self.vm_writeable_memory.saturating_add(writeable);
+        let new_read_only = self.vm_read_only_memory.saturating_add(read_only);
+        let cost = self.gas_schedule.memory_cost(new_writeable as u64, new_read_only as u64)
+            - self.gas_schedule.memory_cost(self.vm_writeable_memory as u64, self.vm_read_only_memory as u64);
+        self.charge_gas(cost)?;
+        self.vm_writeable_memory = new_writeable;
+        self.vm_read_only_memory = new_read_only;
+        Ok(())
+    }
+    /// Accounts for writeable memory allocated by a VM, charging for it according to the gas schedule
+    /// The memory cost is not refunded when the memory is freed
+    pub fn allocate_writeable_memory(&mut self, size: u32) -> Result<(), NeutronError>{
+        self.allocate_memory(size, 0)
+    }
+    /// Accounts for read-only memory mapped by a VM, charging for it according to the gas schedule
+    pub fn allocate_read_only_memory(&mut self, size: u32) -> Result<(), NeutronError>{
+        self.allocate_memory(0, size)
+    }
+    /// Accounts for writeable memory released by a VM when it exits
+    pub fn free_writeable_memory(&mut self, size: u32){
+        self.vm_writeable_memory = self.vm_writeable_memory.saturating_sub(size);
+    }
+    /// Accounts for read-only memory released by a VM when it exits
+    pub fn free_read_only_memory(&mut self, size: u32){
+        self.vm_read_only_memory = self.vm_read_only_memory.saturating_sub(size);
+    }
+    /// The items of the input stack, with the top item last
+    pub fn input_stack(&self) -> &Vec<Vec<u8>>{
+        &self.stacks[self.input_stack_index]
+    }
+    /// The current result map
+    pub fn result_map(&self) -> &HashMap<Vec<u8>, Vec<u8>>{
+        &self.maps[self.top_result_map_index]
+    }
+    /// The total size in bytes of all items on the input stack
+    pub fn input_stack_size(&self) -> u64{
+        self.stacks[self.input_stack_index].iter().map(|v| v.len() as u64).sum()
+    }
+    /// The total size in bytes of all items on the output stack
+    pub fn output_stack_size(&self) -> u64{
+        self.stacks[self.output_stack_index].iter().map(|v| v.len() as u64).sum()
+    }
+    /// The total size in bytes of all keys and values in the input map
+    pub fn input_map_size(&self) -> u64{
+        self.maps[self.top_input_map_index].iter().map(|(k, v)| (k.len() + v.len()) as u64).sum()
+    }
+    /// The number of bytes which can still be pushed to the output stack
+    pub fn output_stack_remaining(&self) -> u64{
+        self.max_costack_size.saturating_sub(self.output_stack_size())
+    }
     pub fn push_output_stack(&mut self, data: &[u8]) -> Result<(), NeutronError>{
+        if data.len() as u64 > self.output_stack_remaining(){
+            return Err(Host(HostError::CostackFull));
+        }
         self.stacks[self.output_stack_index].push(data.to_vec());
         Ok(())
     }
@@ -83,6 +228,30 @@ impl CoData{
             }
         }
     }
+    /// Pops an item from the input stack which must be exactly `size` bytes long
+    fn pop_input_stack_exact(&mut self, size: usize) -> Result<Vec<u8>, NeutronError>{
+        let v = self.pop_input_stack()?;
+        if v.len() < size{
+            return Err(Recoverable(RecoverableError::StackItemTooSmall));
+        }
+        if v.len() > size{
+            return Err(Recoverable(RecoverableError::StackItemTooLarge));
+        }
+        Ok(v)
+    }
+    /// Pops a little endian u64 from the input stack
+    pub fn pop_input_stack_u64(&mut self) -> Result<u64, NeutronError>{
+        let v = self.pop_input_stack_exact(8)?;
+        Ok(u64::from_le_bytes(v.try_into().unwrap()))
+    }
+    /// Pops a NeutronAddress (version followed by address data) from the input stack
+    pub fn pop_input_stack_address(&mut self) -> Result<NeutronAddress, NeutronError>{
+        let v = self.pop_input_stack_exact(4 + 20)?;
+        let mut address = NeutronAddress::default();
+        address.version = u32::from_le_bytes(v[0..4].try_into().unwrap());
+        address.data.copy_from_slice(&v[4..]);
+        Ok(address)
+    }
     pub fn clear_input_stack(&mut self){
         self.stacks[self.input_stack_index].clear();
     }
@@ -111,6 +280,21 @@ impl CoData{
             }
         }
     }
+    /// Swaps the top item of the input stack with the item `index` items below it
+    pub fn swap_input_stack(&mut self, index: u32) -> Result<(), NeutronError>{
+        let stack = &mut self.stacks[self.input_stack_index];
+        if index as usize >= stack.len(){
+            return Err(Recoverable(RecoverableError::ItemDoesntExist));
+        }
+        let top = stack.len() - 1;
+        stack.swap(top, top - index as usize);
+        Ok(())
+    }
+    /// Pushes a copy of the input stack item `index` items below the top to the output stack
+    pub fn dup_input_stack(&mut self, index: u32) -> Result<(), NeutronError>{
+        let item = self.peek_input_stack(index)?;
+        self.push_output_stack(&item)
+    }
 
     pub fn push_output_key(&mut self, key: &[u8], value: &[u8]) -> Result<(), NeutronError>{
         if key[0] == 0{
@@ -205,8 +389,8 @@ impl CoData{
             }
         }
     }
-    pub fn compute_outgoing_transfer_value(&self, token_owner: NeutronAddress, id: u64, _address: NeutronAddress) -> Result<u64, NeutronError>{
-        let address = self.context_stack.last().unwrap().self_address;
+    /// Computes the total value of the transfers created by contexts of the given address which are still pending
+    pub fn compute_outgoing_transfer_value(&self, token_owner: NeutronAddress, id: u64, address: NeutronAddress) -> Result<u64, NeutronError>{
         let key = self.build_transfer_key(token_owner, id);
         let mut value = 0;
         for context in &self.context_stack{
@@ -257,7 +441,11 @@ impl CoData{
     */
 
     /// Pushes a new execution context into the stack
+    /// A nested context can not be given permissions beyond what its parent context may grant
     pub fn push_context(&mut self, context: ExecutionContext) -> Result<(), NeutronError>{
+        if let Some(parent) = self.context_stack.last(){
+            parent.permissions.assert_can_grant(&context.permissions, parent.self_address == context.self_address)?;
+        }
         let mut c = context;
         self.top_input_map_index = self.top_output_map_index; //one below top of stack
         self.top_output_map_index = self.top_result_map_index; //top of stack
@@ -365,7 +553,13 @@ impl CoData{
         self.push_context(c).unwrap();
     }
     /// Creates a new nested context for calling an existing contract. The context stack MUST NOT be empty
-    pub fn create_call(&mut self, address: NeutronAddress, gas_limit: u64, value: u64){
+    /// The new context inherits the permissions of the calling context
+    pub fn create_call(&mut self, address: NeutronAddress, gas_limit: u64, value: u64) -> Result<(), NeutronError>{
+        let permissions = self.peek_context(0)?.permissions;
+        self.create_call_with_permissions(address, gas_limit, value, permissions)
+    }
+    /// Creates a new nested context for calling an existing contract with the given permissions. The context stack MUST NOT be empty
+    pub fn create_call_with_permissions(&mut self, address: NeutronAddress, gas_limit: u64, value: u64, permissions: ContextPermissions) -> Result<(), NeutronError>{
         assert!(self.context_stack.len() > 0);
         let mut c = ExecutionContext::default();
         c.self_address = address.clone();
@@ -373,11 +567,14 @@ impl CoData{
         c.value_sent = value;
         c.sender = self.peek_context(0).unwrap().self_address.clone();
         c.origin = self.context_stack.get(0).unwrap().sender.clone();
+        c.origin_long = self.context_stack.get(0).unwrap().sender_long.clone();
+        c.permissions = permissions;
         c.execution_type = ExecutionType::Call;
-        self.push_context(c).unwrap();
+        self.push_context(c)
     }
     /// Creates a new nested context for deploying a contract. The context stack MUST NOT be empty
-    pub fn create_deploy(&mut self, address: NeutronAddress, gas_limit: u64, value: u64){
+    /// The new context inherits the permissions of the deploying context
+    pub fn create_deploy(&mut self, address: NeutronAddress, gas_limit: u64, value: u64) -> Result<(), NeutronError>{
         assert!(self.context_stack.len() > 0);
         let mut c = ExecutionContext::default();
         c.self_address = address.clone();
@@ -385,8 +582,10 @@ impl CoData{
         c.value_sent = value;
         c.sender = self.peek_context(0).unwrap().self_address.clone();
         c.origin = self.context_stack.get(0).unwrap().sender.clone();
+        c.origin_long = self.context_stack.get(0).unwrap().sender_long.clone();
+        c.permissions = self.peek_context(0).unwrap().permissions;
         c.execution_type = ExecutionType::Deploy;
-        self.push_context(c).unwrap();
+        self.push_context(c)
     }
 }
 
@@ -468,4 +667,177 @@ mod tests {
         manager.pop_context().unwrap();
         assert_eq!(manager.peek_result_key(&key).unwrap()[0], 5); //note: unsure if this behavior is correct
     }
+    #[test]
+    fn test_permission_propagation(){
+        let mut codata = CoData::new();
+        let mut parent = ExecutionContext::default();
+        parent.self_address.version = 1;
+        let mut other = NeutronAddress::default();
+        other.version = 2;
+
+        //pure can only make pure calls
+        parent.permissions = ContextPermissions::pure_call();
+        codata.push_context(parent.clone()).unwrap();
+        assert!(codata.create_call_with_permissions(other, 0, 0, ContextPermissions::pure_call()).is_ok());
+        codata.pop_context().unwrap();
+        assert_eq!(codata.create_call_with_permissions(other, 0, 0, ContextPermissions::immutable_call()),
+            Err(Recoverable(RecoverableError::RequiresPermissionExternalAccess)));
+        assert_eq!(codata.context_count(), 1);
+        codata.pop_context().unwrap();
+
+        //immutable can only make immutable and pure calls
+        parent.permissions = ContextPermissions::immutable_call();
+        codata.push_context(parent.clone()).unwrap();
+        assert!(codata.create_call(other, 0, 0).is_ok());
+        codata.pop_context().unwrap();
+        assert_eq!(codata.create_call_with_permissions(parent.self_address, 0, 0, ContextPermissions::isolated_call()),
+            Err(Recoverable(RecoverableError::RequiresPermissionSelfMod)));
+        assert_eq!(codata.create_call_with_permissions(other, 0, 0, ContextPermissions::mutable_call()),
+            Err(Recoverable(RecoverableError::RequiresPermissionExternalMod)));
+        codata.pop_context().unwrap();
+
+        //isolated can only touch its own address, but can make pure calls to other addresses
+        parent.permissions = ContextPermissions::isolated_call();
+        codata.push_context(parent.clone()).unwrap();
+        assert!(codata.create_call(parent.self_address, 0, 0).is_ok());
+        codata.pop_context().unwrap();
+        assert!(codata.create_call_with_permissions(other, 0, 0, ContextPermissions::pure_call()).is_ok());
+        codata.pop_context().unwrap();
+        assert_eq!(codata.create_call(other, 0, 0), Err(Recoverable(RecoverableError::RequiresPermissionExternalMod)));
+        codata.pop_context().unwrap();
+
+        //mutable can make all calls
+        parent.permissions = ContextPermissions::mutable_call();
+        codata.push_context(parent.clone()).unwrap();
+        assert!(codata.create_call(other, 0, 0).is_ok());
+        codata.pop_context().unwrap();
+        assert!(codata.create_call_with_permissions(parent.self_address, 0, 0, ContextPermissions::isolated_call()).is_ok());
+    }
+    #[test]
+    fn test_ignored_permissions(){
+        let mut codata = CoData::new();
+        let mut context = ExecutionContext::default();
+        context.permissions = ContextPermissions::pure_call();
+        codata.push_context(context).unwrap();
+        let result: Result<(), NeutronError> = codata.with_ignored_permissions(|c|{
+            assert!(c.permissions().modify_self);
+            Err(Recoverable(RecoverableError::ItemDoesntExist))
+        });
+        assert!(result.is_err());
+        //permissions are restored even when the operation fails
+        assert!(!codata.ignore_permissions);
+        assert!(!codata.permissions().access_self);
+        //an outer setting is kept
+        codata.ignore_permissions = true;
+        codata.with_ignored_permissions(|_| {});
+        assert!(codata.ignore_permissions);
+    }
+    #[test]
+    fn test_long_addresses(){
+        let mut codata = CoData::new();
+        let mut sender = NeutronAddress::default();
+        sender.version = 3;
+        sender.data[0] = 1;
+        let mut contract = NeutronAddress::default();
+        contract.version = 2;
+        codata.create_top_level_call(contract, sender, 0, 0);
+        //without a long form, the short address data is used
+        let mut expected = vec![3, 0, 0, 0];
+        expected.extend_from_slice(&sender.data);
+        assert_eq!(codata.current_context().origin_long_address(), expected);
+        codata.pop_context().unwrap();
+
+        let mut context = ExecutionContext::default();
+        context.self_address = contract;
+        context.sender = sender;
+        context.sender_long = vec![9; 32];
+        context.origin = sender;
+        context.origin_long = vec![9; 32];
+        context.permissions = ContextPermissions::mutable_call();
+        codata.push_context(context).unwrap();
+        codata.create_call(contract, 0, 0).unwrap();
+        let mut expected = vec![3, 0, 0, 0];
+        expected.extend_from_slice(&[9; 32]);
+        assert_eq!(codata.current_context().origin_long_address(), expected);
+        //the sender of a nested call is a contract, which has no long form
+        let mut expected = vec![2, 0, 0, 0];
+        expected.extend_from_slice(&contract.data);
+        assert_eq!(codata.current_context().sender_long_address(), expected);
+    }
+    #[test]
+    fn test_permission_flags(){
+        assert_eq!(ContextPermissions::mutable_call().to_flags(), 0b1111);
+        assert_eq!(ContextPermissions::immutable_call().to_flags(), 0b1100);
+        assert_eq!(ContextPermissions::isolated_call().to_flags(), 0b1001);
+        assert_eq!(ContextPermissions::pure_call().to_flags(), 0);
+    }
+    #[test]
+    fn test_costack_operations(){
+        let mut codata = CoData::new();
+        codata.push_context(ExecutionContext::default()).unwrap();
+        codata.push_output_stack(&[1]).unwrap();
+        codata.push_output_stack(&[2, 2]).unwrap();
+        codata.push_output_stack(&[3, 3, 3]).unwrap();
+        codata.flip_stacks();
+        assert_eq!(codata.input_stack_size(), 6);
+        assert_eq!(codata.peek_input_stack(2).unwrap(), vec![1]);
+        assert!(codata.peek_input_stack(3).is_err());
+
+        codata.swap_input_stack(2).unwrap();
+        assert_eq!(codata.input_stack(), &vec![vec![3, 3, 3], vec![2, 2], vec![1]]);
+        codata.swap_input_stack(0).unwrap();
+        assert_eq!(codata.peek_input_stack(0).unwrap(), vec![1]);
+        assert_eq!(codata.swap_input_stack(3), Err(Recoverable(RecoverableError::ItemDoesntExist)));
+
+        codata.dup_input_stack(1).unwrap();
+        assert_eq!(codata.input_stack().len(), 3);
+        assert_eq!(codata.output_stack_size(), 2);
+        assert!(codata.dup_input_stack(3).is_err());
+    }
+    #[test]
+    fn test_writeable_memory_cost(){
+        let mut codata = CoData::new();
+        codata.gas_remaining = 10000;
+        codata.gas_schedule.vm_costs.insert(VM_COST_WRITEABLE_MEMORY_BYTE, 2);
+        codata.allocate_writeable_memory(100).unwrap();
+        assert_eq!(codata.gas_remaining, 10000 - 200);
+        //the quadratic cost grows with the memory already allocated
+        codata.gas_schedule.vm_costs.insert(VM_COST_MEMORY_QUADRATIC_DIVISOR, 100);
+        codata.allocate_writeable_memory(100).unwrap();
+        assert_eq!(codata.gas_remaining, 10000 - 200 - (200 + 400 - 100));
+        assert_eq!(codata.vm_writeable_memory, 200);
+        codata.free_writeable_memory(150);
+        assert_eq!(codata.vm_writeable_memory, 50);
+        assert_eq!(codata.allocate_writeable_memory(10000), Err(Unrecoverable(UnrecoverableError::OutOfGas)));
+        assert_eq!(codata.vm_writeable_memory, 50);
+    }
+    #[test]
+    fn test_memory_quadratic_threshold(){
+        let mut codata = CoData::new();
+        codata.gas_remaining = 100000;
+        codata.gas_schedule.vm_costs.insert(VM_COST_READ_ONLY_MEMORY_BYTE, 1);
+        codata.gas_schedule.vm_costs.insert(VM_COST_MEMORY_QUADRATIC_DIVISOR, 10);
+        codata.gas_schedule.vm_costs.insert(VM_COST_MEMORY_QUADRATIC_THRESHOLD, 1000);
+        codata.allocate_read_only_memory(600).unwrap();
+        assert_eq!(codata.gas_remaining, 100000 - 600);
+        //writeable memory counts towards the threshold, but costs nothing per byte in this schedule
+        codata.allocate_writeable_memory(600).unwrap();
+        assert_eq!(codata.gas_remaining, 100000 - 600 - 200 * 200 / 10);
+        assert_eq!(codata.vm_read_only_memory, 600);
+        codata.free_read_only_memory(600);
+        assert_eq!(codata.vm_read_only_memory, 0);
+    }
+    #[test]
+    fn test_costack_size_limit(){
+        let mut codata = CoData::new();
+        codata.max_costack_size = 10;
+        codata.push_output_stack(&[0; 6]).unwrap();
+        assert_eq!(codata.output_stack_remaining(), 4);
+        assert_eq!(codata.push_output_stack(&[0; 5]), Err(Host(HostError::CostackFull)));
+        codata.push_output_stack(&[0; 4]).unwrap();
+        assert_eq!(codata.output_stack_remaining(), 0);
+        //inputs do not count towards the limit of the output stack
+        codata.flip_stacks();
+        assert_eq!(codata.output_stack_remaining(), 10);
+    }
 }
\ No newline at end of file
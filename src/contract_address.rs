@@ -0,0 +1,101 @@
+//! Deterministic derivation of the addresses given to deployed contracts
+//!
+//! Addresses are derived either from the sender and its deployment nonce (tracked in GlobalState), or from the sender,
+//! the hash of the deployed code and a salt. In both cases the address data is the first 20 bytes of a SHA-256 hash
+
+use crate::codata::*;
+use crate::element_interfaces::storage::*;
+use crate::neutronerror::*;
+use crate::AddressDecoding;
+use neutron_common::*;
+use ring::digest::{digest, SHA256};
+
+/// Domain separators, so that nonce derived and salted addresses can never be the same
+const NONCE_DERIVATION: u8 = 0;
+const SALTED_DERIVATION: u8 = 1;
+
+fn address_from_preimage(preimage: &[u8], version: u32) -> NeutronAddress {
+    let hash = digest(&SHA256, preimage);
+    let mut address = NeutronAddress::default();
+    address.version = version;
+    address.data.copy_from_slice(&hash.as_ref()[0..20]);
+    address
+}
+
+/// Derives the address of a contract deployed by `sender` using the given deployment nonce of the sender
+pub fn derive_contract_address(sender: &NeutronAddress, nonce: u64, version: u32) -> NeutronAddress {
+    let mut preimage = vec![NONCE_DERIVATION];
+    preimage.extend(sender.decode());
+    preimage.extend(&nonce.to_le_bytes());
+    address_from_preimage(&preimage, version)
+}
+
+/// Derives the address of a contract deployed by `sender` from the deployed code and a salt chosen by the sender
+/// This does not depend on any state, so the same sender can only deploy the same code with the same salt once
+pub fn derive_salted_contract_address(sender: &NeutronAddress, code: &[u8], salt: &[u8], version: u32) -> NeutronAddress {
+    let mut preimage = vec![SALTED_DERIVATION];
+    preimage.extend(sender.decode());
+    preimage.extend(digest(&SHA256, code).as_ref());
+    preimage.extend(salt);
+    address_from_preimage(&preimage, version)
+}
+
+/// Gets the address which the next nonce based deployment by `sender` will be given, without using up the nonce
+pub fn peek_next_contract_address(
+    storage: &mut dyn GlobalState,
+    codata: &mut CoData,
+    sender: NeutronAddress,
+    version: u32,
+) -> Result<NeutronAddress, NeutronError> {
+    let nonce = storage.get_deploy_nonce(codata, sender)?;
+    Ok(derive_contract_address(&sender, nonce, version))
+}
+
+/// Gets the address for the next nonce based deployment by `sender` and increments the deployment nonce of `sender`
+pub fn next_contract_address(
+    storage: &mut dyn GlobalState,
+    codata: &mut CoData,
+    sender: NeutronAddress,
+    version: u32,
+) -> Result<NeutronAddress, NeutronError> {
+    let nonce = storage.increment_deploy_nonce(codata, sender)?;
+    Ok(derive_contract_address(&sender, nonce, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryGlobalState;
+
+    #[test]
+    fn test_nonce_addresses() {
+        let mut sender = NeutronAddress::default();
+        sender.version = 2;
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut codata = CoData::new();
+
+        let expected = derive_contract_address(&sender, 0, 2);
+        assert_eq!(peek_next_contract_address(&mut db, &mut codata, sender, 2).unwrap(), expected);
+        assert_eq!(next_contract_address(&mut db, &mut codata, sender, 2).unwrap(), expected);
+        let second = next_contract_address(&mut db, &mut codata, sender, 2).unwrap();
+        assert_eq!(second, derive_contract_address(&sender, 1, 2));
+        assert_ne!(second, expected);
+        assert_eq!(second.version, 2);
+
+        //nonces are tracked per sender
+        let mut other = sender;
+        other.data[0] = 1;
+        assert_eq!(db.get_deploy_nonce(&mut codata, other).unwrap(), 0);
+        assert_eq!(db.get_deploy_nonce(&mut codata, sender).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_salted_addresses() {
+        let sender = NeutronAddress::default();
+        let a = derive_salted_contract_address(&sender, &[1, 2, 3], &[0], 2);
+        assert_eq!(a, derive_salted_contract_address(&sender, &[1, 2, 3], &[0], 2));
+        assert_ne!(a, derive_salted_contract_address(&sender, &[1, 2, 3], &[1], 2));
+        assert_ne!(a, derive_salted_contract_address(&sender, &[1, 2, 4], &[0], 2));
+    }
+}
@@ -92,13 +92,13 @@ impl GlobalState for MemoryGlobalState{
         codata.permissions().assert_has_self_access()?;
         self.read_key(&codata.peek_context(0).unwrap().self_address, key)
     }
-    fn private_store_state_external(&mut self, codata: &mut CoData, _address: NeutronAddress, key: &[u8], value: &[u8]) -> Result<(), NeutronError> {
+    fn private_store_state_external(&mut self, codata: &mut CoData, address: NeutronAddress, key: &[u8], value: &[u8]) -> Result<(), NeutronError> {
         codata.permissions().assert_has_external_modification()?;
-        self.write_key(&codata.peek_context(0).unwrap().self_address, &key, value)
+        self.write_key(&address, &key, value)
     }
-    fn private_load_state_external(&mut self, codata: &mut CoData, _address: NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError> {
+    fn private_load_state_external(&mut self, codata: &mut CoData, address: NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError> {
         codata.permissions().assert_has_external_access()?;
-        self.read_key(&codata.peek_context(0).unwrap().self_address, &key)
+        self.read_key(&address, &key)
     }
 
     fn create_checkpoint(&mut self, _codata: &mut CoData) -> Result<(), NeutronError>{
@@ -112,10 +112,13 @@ impl GlobalState for MemoryGlobalState{
     fn commit_checkpoint(&mut self, _codata: &mut CoData) -> Result<(), NeutronError>{
         self.commit_single_checkpoint()
     }
+    fn checkpoint_writes(&mut self, _codata: &mut CoData) -> Result<StateWrites, NeutronError>{
+        Ok(self.checkpoints.last().cloned().unwrap_or_default())
+    }
 }
 
 impl MemoryGlobalState{
-    pub fn read_key(&mut self, address: &NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError>{
+    pub fn read_key(&self, address: &NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError>{
         for checkpoint in self.checkpoints.iter().rev(){
             match checkpoint.get(address){
                 Some(kv) => {
@@ -176,24 +179,18 @@ impl MemoryGlobalState{
     }
     pub fn commit_single_checkpoint(&mut self) -> Result<(), NeutronError>{
         let mut collapsed = HashMap::new();
-        let mut kv_top = self.checkpoints.pop().unwrap();
-        let mut kv_bottom = self.checkpoints.pop().unwrap();
-        for (key, value) in kv_bottom.drain(){
-            collapsed.insert(key, value);
-        }
-        for (key, value) in kv_top.drain(){
-            collapsed.insert(key, value);
-        }
+        let kv_top = self.checkpoints.pop().unwrap();
+        let kv_bottom = self.checkpoints.pop().unwrap();
+        merge_writes(&mut collapsed, kv_bottom);
+        merge_writes(&mut collapsed, kv_top);
         self.checkpoints.push(collapsed);
         
         Ok(())
     }
     pub fn collapse_checkpoints(&mut self) -> Result<(), NeutronError>{
         let mut collapsed = HashMap::new();
-        for kv in self.checkpoints.iter_mut(){
-            for (key, value) in kv.drain(){
-                collapsed.insert(key, value);
-            }
+        for kv in self.checkpoints.drain(..){
+            merge_writes(&mut collapsed, kv);
         }
         self.checkpoints.clear();
         self.checkpoints.push(collapsed);
@@ -220,6 +217,97 @@ impl MemoryGlobalState{
     pub fn clear_checkpoints(&mut self){
         self.checkpoints.clear();
     }
+    /// Removes the most recent checkpoint, returning the modifications made since it was created
+    pub fn take_checkpoint(&mut self) -> Result<StateWrites, NeutronError>{
+        match self.checkpoints.pop(){
+            Some(writes) => Ok(writes),
+            None => Err(NeutronError::Unrecoverable(UnrecoverableError::DeveloperError))
+        }
+    }
+}
+
+impl StateSnapshot for MemoryGlobalState{
+    fn read_raw_key(&self, address: &NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError>{
+        self.read_key(address, key)
+    }
+}
+
+/// State which records writes on top of a base state that is only read, so that several executions can share the same base state
+/// without copying it. The base state is not modified, the writes are instead taken with take_writes once the execution is done
+pub struct OverlayGlobalState<'a, S: StateSnapshot + ?Sized>{
+    base: &'a S,
+    writes: MemoryGlobalState
+}
+
+impl<'a, S: StateSnapshot + ?Sized> OverlayGlobalState<'a, S>{
+    pub fn new(base: &'a S) -> OverlayGlobalState<'a, S>{
+        let mut writes = MemoryGlobalState::default();
+        //the bottom checkpoint holds all writes made on top of the base state
+        writes.checkpoints.push(HashMap::new());
+        OverlayGlobalState{
+            base: base,
+            writes: writes
+        }
+    }
+    pub fn read_key(&self, address: &NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError>{
+        match self.writes.read_key(address, key){
+            Ok(v) => Ok(v),
+            Err(_) => self.base.read_raw_key(address, key)
+        }
+    }
+    /// Returns all writes made on top of the base state, including those of checkpoints which are still open
+    pub fn take_writes(mut self) -> Result<StateWrites, NeutronError>{
+        self.writes.collapse_checkpoints()?;
+        self.writes.take_checkpoint()
+    }
+}
+
+impl<'a, S: StateSnapshot + ?Sized> GlobalState for OverlayGlobalState<'a, S>{
+    fn store_state(&mut self, codata: &mut CoData, key: &[u8], value: &[u8]) -> Result<(), NeutronError>{
+        self.writes.store_state(codata, key, value)
+    }
+    fn load_state(&mut self, codata: &mut CoData, key: &[u8]) -> Result<Vec<u8>, NeutronError>{
+        codata.permissions().assert_has_self_access()?;
+        self.read_key(&codata.peek_context(0).unwrap().self_address, &self.writes.create_user_key(key))
+    }
+    fn key_exists(&mut self, codata: &mut CoData, key: &[u8]) -> Result<bool, NeutronError>{
+        self.writes.key_exists(codata, key)
+    }
+
+    fn private_store_state(&mut self, codata: &mut CoData, key: &[u8], value: &[u8]) -> Result<(), NeutronError>{
+        self.writes.private_store_state(codata, key, value)
+    }
+    fn private_load_state(&mut self, codata: &mut CoData, key: &[u8]) -> Result<Vec<u8>, NeutronError>{
+        codata.permissions().assert_has_self_access()?;
+        self.read_key(&codata.peek_context(0).unwrap().self_address, key)
+    }
+    fn private_store_state_external(&mut self, codata: &mut CoData, address: NeutronAddress, key: &[u8], value: &[u8]) -> Result<(), NeutronError> {
+        self.writes.private_store_state_external(codata, address, key, value)
+    }
+    fn private_load_state_external(&mut self, codata: &mut CoData, address: NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError> {
+        codata.permissions().assert_has_external_access()?;
+        self.read_key(&address, &key)
+    }
+
+    fn create_checkpoint(&mut self, codata: &mut CoData) -> Result<(), NeutronError>{
+        self.writes.create_checkpoint(codata)
+    }
+    fn revert_checkpoint(&mut self, codata: &mut CoData) -> Result<(), NeutronError>{
+        self.writes.revert_checkpoint(codata)
+    }
+    fn commit_checkpoint(&mut self, codata: &mut CoData) -> Result<(), NeutronError>{
+        self.writes.commit_checkpoint(codata)
+    }
+    fn checkpoint_writes(&mut self, codata: &mut CoData) -> Result<StateWrites, NeutronError>{
+        self.writes.checkpoint_writes(codata)
+    }
+}
+
+/// Merges `writes` into `into` key by key, with `writes` taking precedence
+pub fn merge_writes(into: &mut StateWrites, writes: StateWrites){
+    for (address, kv) in writes{
+        into.entry(address).or_insert_with(HashMap::new).extend(kv);
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -252,6 +340,121 @@ mod tests {
         assert!(v == vec![8, 8, 8, 8]);
     }
     
+    #[test]
+    fn test_commit_merges_keys(){
+        let mut a = NeutronAddress::default();
+        a.version=100;
+        let mut db = MemoryGlobalState::default();
+        assert!(db.checkpoint().is_ok());
+        assert!(db.write_key(&a, &[1], &[1]).is_ok());
+        assert!(db.checkpoint().is_ok());
+        assert!(db.write_key(&a, &[2], &[2]).is_ok());
+        assert!(db.commit_single_checkpoint().is_ok());
+        assert_eq!(db.read_key(&a, &[1]).unwrap(), vec![1]);
+        assert!(db.checkpoint().is_ok());
+        assert!(db.write_key(&a, &[3], &[3]).is_ok());
+        assert!(db.commit().is_ok());
+        assert_eq!(db.read_key(&a, &[1]).unwrap(), vec![1]);
+        assert_eq!(db.read_key(&a, &[2]).unwrap(), vec![2]);
+        assert_eq!(db.read_key(&a, &[3]).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn test_overlay(){
+        use crate::interface::*;
+        let mut a = NeutronAddress::default();
+        a.version=100;
+        let mut db = MemoryGlobalState::default();
+        assert!(db.checkpoint().is_ok());
+        assert!(db.write_key(&a, &[95, 1], &[1]).is_ok());
+        assert!(db.write_key(&a, &[95, 2], &[2]).is_ok());
+
+        let mut codata = CoData::new();
+        let mut context = ExecutionContext::default();
+        context.self_address = a;
+        context.permissions = ContextPermissions::mutable_call();
+        codata.push_context(context).unwrap();
+        let mut overlay = OverlayGlobalState::new(&db);
+        assert_eq!(overlay.load_state(&mut codata, &[1]).unwrap(), vec![1]);
+        assert!(overlay.store_state(&mut codata, &[2], &[3]).is_ok());
+        assert!(overlay.create_checkpoint(&mut codata).is_ok());
+        assert!(overlay.store_state(&mut codata, &[4], &[4]).is_ok());
+        assert!(overlay.revert_checkpoint(&mut codata).is_ok());
+        assert_eq!(overlay.load_state(&mut codata, &[2]).unwrap(), vec![3]);
+        assert!(overlay.load_state(&mut codata, &[4]).is_err());
+        let writes = overlay.take_writes().unwrap();
+
+        //the base state is unchanged until the writes are applied to it
+        assert_eq!(db.read_key(&a, &[95, 2]).unwrap(), vec![2]);
+        assert!(db.apply_writes(&mut codata, writes).is_ok());
+        assert_eq!(db.read_key(&a, &[95, 1]).unwrap(), vec![1]);
+        assert_eq!(db.read_key(&a, &[95, 2]).unwrap(), vec![3]);
+        assert!(db.read_key(&a, &[95, 4]).is_err());
+    }
+
+    /// Calls a GlobalState function as an element call of the top context, with `inputs` given in the documented argument order
+    fn token_syscall(db: &mut MemoryGlobalState, codata: &mut CoData, function: GlobalStateFunctions, inputs: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, NeutronError>{
+        for input in inputs.iter().rev(){
+            codata.push_output_stack(input)?;
+        }
+        codata.enter_element();
+        let result = db.try_syscall(codata, GLOBAL_STORAGE_FEATURE, function as u32);
+        codata.exit_element();
+        result?;
+        Ok(codata.input_stack().clone())
+    }
+
+    #[test]
+    fn test_token_transfers(){
+        use crate::AddressDecoding;
+        use crate::interface::*;
+        use crate::test_helpers::address;
+        let (owner, a, b) = (address(9), address(1), address(2));
+        let id = 1u64.to_le_bytes().to_vec();
+        let amount = |v: u64| v.to_le_bytes().to_vec();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        db.write_key(&owner, &build_token_key(a, 1), &amount(100)).unwrap();
+        db.write_key(&owner, &build_token_key(b, 1), &amount(10)).unwrap();
+
+        let mut codata = CoData::new();
+        let mut context = ExecutionContext::default();
+        context.self_address = a;
+        context.permissions = ContextPermissions::mutable_call();
+        codata.push_context(context.clone()).unwrap();
+        let balance_of = |db: &mut MemoryGlobalState, codata: &mut CoData, address: NeutronAddress| {
+            token_syscall(db, codata, GlobalStateFunctions::GetTokenBalance, &[owner.decode(), id.clone(), address.decode()]).unwrap()
+        };
+        assert_eq!(balance_of(&mut db, &mut codata, a), vec![amount(100)]);
+
+        //pending transfers only count against the balance of their sender
+        token_syscall(&mut db, &mut codata, GlobalStateFunctions::CreateTokenTransfer, &[owner.decode(), id.clone(), amount(30)]).unwrap();
+        assert_eq!(balance_of(&mut db, &mut codata, a), vec![amount(70)]);
+        assert_eq!(balance_of(&mut db, &mut codata, b), vec![amount(10)]);
+        assert_eq!(
+            token_syscall(&mut db, &mut codata, GlobalStateFunctions::CreateTokenTransfer, &[owner.decode(), id.clone(), amount(80)]),
+            Err(NeutronError::Recoverable(RecoverableError::LowTokenBalance))
+        );
+
+        context.self_address = b;
+        context.sender = a;
+        codata.push_context(context.clone()).unwrap();
+        assert_eq!(token_syscall(&mut db, &mut codata, GlobalStateFunctions::ClaimTokenTransfer, &[owner.decode(), id.clone()]).unwrap(), vec![amount(30)]);
+        assert_eq!(db.read_key(&owner, &build_token_key(a, 1)).unwrap(), amount(70));
+        assert_eq!(db.read_key(&owner, &build_token_key(b, 1)).unwrap(), amount(40));
+
+        context.permissions = ContextPermissions::immutable_call();
+        codata.push_context(context).unwrap();
+        assert_eq!(
+            token_syscall(&mut db, &mut codata, GlobalStateFunctions::ClaimTokenTransfer, &[owner.decode(), id.clone()]),
+            Err(NeutronError::Recoverable(RecoverableError::RequiresPermissionExternalMod))
+        );
+        assert_eq!(
+            token_syscall(&mut db, &mut codata, GlobalStateFunctions::CreateTokenTransfer, &[owner.decode(), id.clone(), amount(10)]),
+            Err(NeutronError::Recoverable(RecoverableError::RequiresPermissionExternalMod))
+        );
+    }
+
     #[test]
     fn test_storage(){
         let mut a = NeutronAddress::default();
@@ -0,0 +1,419 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::contract_address::*;
+use crate::element_interfaces::storage::GlobalState;
+use crate::interface::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use crate::AddressDecoding;
+use neutron_common::*;
+/*
+## Call Contract
+
+ID: 5
+
+Functions:
+
+* [0] Available()
+* [1] CallContract(address: stack NeutronAddress, gas_limit: stack u64, value: stack u64, ...arguments) -> (status: u32, ...outputs)
+* [2] CallContractImmutable(address: stack NeutronAddress, gas_limit: stack u64, value: stack u64, ...arguments) -> (status: u32, ...outputs)
+* [3] CallContractPure(address: stack NeutronAddress, gas_limit: stack u64, value: stack u64, ...arguments) -> (status: u32, ...outputs)
+* [4] DeployContract(gas_limit: stack u64, value: stack u64, ...arguments, code: comap "!.c", data: comap "!.d") -> (status: u32, ...outputs)
+* [5] DeployContractSalted(salt: stack bytes, gas_limit: stack u64, value: stack u64, ...arguments, code: comap "!.c", data: comap "!.d") -> (status: u32, ...outputs)
+* [6] GetDeployAddress() -> (address: stack NeutronAddress)
+* [7] GetSaltedDeployAddress(salt: stack bytes, code: comap "!.c") -> (address: stack NeutronAddress)
+
+CallContract pops its own arguments from the costack, and everything left on the costack after that is passed on as the
+input costack of the called contract. The caller's output comap becomes the input comap of the called contract as usual.
+
+Upon return the status code of the called contract is given as the result of the element call, the output costack of the
+called contract becomes the input costack of the caller, and the output comap of the called contract becomes the result comap of the caller.
+
+CallContract gives the called contract the same permissions as the caller, while CallContractImmutable and CallContractPure
+restrict the called contract to immutable and pure permissions respectively. The call fails if the caller is not allowed to
+grant the permissions in question (for instance, a pure contract can only make pure calls).
+
+DeployContract deploys the code and data in the caller's output comap to an address derived from the caller's address and its
+deployment nonce, and DeployContractSalted to an address derived from the caller's address, the code and the salt instead.
+Deployed contracts use the same VM as the caller. GetDeployAddress and GetSaltedDeployAddress give the address the respective
+deployment would use, so that it can be known before deploying. A deployment fails if a contract already exists at the address.
+Deploying requires self modification permission, as well as permission to grant the permissions of the caller to another address.
+GetDeployAddress requires self access, as it reads the deployment nonce of the caller.
+*/
+
+pub const CALL_CONTRACT_FEATURE: u32 = 5;
+
+#[derive(FromPrimitive, Clone, Copy)]
+pub enum CallContractFunctions {
+    Available = 0, //reserved??
+    CallContract = 1,
+    CallContractImmutable = 2,
+    CallContractPure = 3,
+    DeployContract = 4,
+    DeployContractSalted = 5,
+    GetDeployAddress = 6,
+    GetSaltedDeployAddress = 7,
+}
+
+#[derive(Default)]
+pub struct ContractCaller {}
+
+impl ElementAPI for ContractCaller {
+    fn system_call(
+        &mut self,
+        callsystem: &CallSystem,
+        codata: &mut CoData,
+        feature: u32,
+        function: u32,
+    ) -> Result<ElementResult, NeutronError> {
+        if feature != CALL_CONTRACT_FEATURE {
+            return Ok(ElementResult::Result(0));
+        }
+        let f = num::FromPrimitive::from_u32(function);
+        if f.is_none() {
+            return Err(Recoverable(RecoverableError::InvalidSystemFunction));
+        }
+        let f = f.unwrap();
+        match f {
+            CallContractFunctions::Available => Ok(ElementResult::Result(1)),
+            CallContractFunctions::CallContract => {
+                let permissions = codata.current_context().permissions;
+                call_contract(codata, permissions)
+            }
+            CallContractFunctions::CallContractImmutable => call_contract(codata, ContextPermissions::immutable_call()),
+            CallContractFunctions::CallContractPure => call_contract(codata, ContextPermissions::pure_call()),
+            CallContractFunctions::DeployContract => {
+                assert_can_deploy(codata)?;
+                let gas_limit = codata.pop_input_stack_u64()?;
+                let value = codata.pop_input_stack_u64()?;
+                let sender = codata.current_context().self_address;
+                let address = {
+                    let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+                    let address = peek_next_contract_address(&mut **storage, codata, sender, sender.version)?;
+                    assert_address_unused(&mut **storage, codata, address)?;
+                    next_contract_address(&mut **storage, codata, sender, sender.version)?;
+                    address
+                };
+                deploy_contract(codata, address, gas_limit, value)
+            }
+            CallContractFunctions::DeployContractSalted => {
+                assert_can_deploy(codata)?;
+                let salt = codata.pop_input_stack()?;
+                let gas_limit = codata.pop_input_stack_u64()?;
+                let value = codata.pop_input_stack_u64()?;
+                let address = salted_address(codata, &salt)?;
+                {
+                    let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+                    assert_address_unused(&mut **storage, codata, address)?;
+                }
+                deploy_contract(codata, address, gas_limit, value)
+            }
+            CallContractFunctions::GetDeployAddress => {
+                codata.permissions().assert_has_self_access()?;
+                let sender = codata.current_context().self_address;
+                let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+                let address = peek_next_contract_address(&mut **storage, codata, sender, sender.version)?;
+                codata.push_output_stack(&address.decode())?;
+                Ok(ElementResult::Result(0))
+            }
+            CallContractFunctions::GetSaltedDeployAddress => {
+                let salt = codata.pop_input_stack()?;
+                let address = salted_address(codata, &salt)?;
+                codata.push_output_stack(&address.decode())?;
+                Ok(ElementResult::Result(0))
+            }
+        }
+    }
+}
+
+fn salted_address(codata: &mut CoData, salt: &[u8]) -> Result<NeutronAddress, NeutronError> {
+    let sender = codata.current_context().self_address;
+    let code = codata.peek_input_key("!.c".as_bytes())?;
+    Ok(derive_salted_contract_address(&sender, &code, salt, sender.version))
+}
+
+/// Checks that the current context may deploy a contract, before the deployment nonce is used up
+/// The deployed contract is given the permissions of the caller at another address, so this is checked here rather than only once
+/// the context of the deployment is pushed
+fn assert_can_deploy(codata: &CoData) -> Result<(), NeutronError> {
+    let permissions = codata.permissions();
+    permissions.assert_has_self_modification()?;
+    permissions.assert_can_grant(&permissions, false)
+}
+
+/// Fails with ContractAddressInUse if a contract has already been deployed to `address`
+/// The code of the contract is checked regardless of the permissions of the current context, as when it is loaded
+fn assert_address_unused(storage: &mut dyn GlobalState, codata: &mut CoData, address: NeutronAddress) -> Result<(), NeutronError> {
+    let existing = codata.with_ignored_permissions(|codata| storage.private_load_state_external(codata, address, &[0x02, 0]));
+    if existing.is_ok() {
+        return Err(Host(HostError::ContractAddressInUse));
+    }
+    Ok(())
+}
+
+/// Pushes the context of a new call or deployment using `push`
+/// The context is pushed with the element exited, so that the caller's output comap becomes the input comap of the new context.
+/// Remaining inputs are moved so that they become the input costack of the new context once the element is exited
+fn push_call_context<F>(codata: &mut CoData, push: F) -> Result<ElementResult, NeutronError>
+where
+    F: FnOnce(&mut CoData) -> Result<(), NeutronError>,
+{
+    // Exiting and entering the element clears both costacks, so the inputs are set aside meanwhile
+    let inputs = codata.input_stack().clone();
+    codata.exit_element();
+    let pushed = push(codata);
+    codata.enter_element();
+    pushed?;
+    for input in inputs {
+        codata.push_output_stack(&input)?;
+    }
+    Ok(ElementResult::NewCall)
+}
+
+fn deploy_contract(codata: &mut CoData, address: NeutronAddress, gas_limit: u64, value: u64) -> Result<ElementResult, NeutronError> {
+    push_call_context(codata, |codata| codata.create_deploy(address, gas_limit, value))
+}
+
+fn call_contract(codata: &mut CoData, permissions: ContextPermissions) -> Result<ElementResult, NeutronError> {
+    let address = codata.pop_input_stack_address()?;
+    let gas_limit = codata.pop_input_stack_u64()?;
+    let value = codata.pop_input_stack_u64()?;
+    push_call_context(codata, |codata| {
+        codata.create_call_with_permissions(address, gas_limit, value, permissions)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryGlobalState;
+    use crate::element_interfaces::storage::*;
+    use crate::interface::*;
+    use crate::manager::*;
+    use crate::test_helpers::*;
+    use crate::vmmanager::*;
+    use std::cell::RefCell;
+
+    /// Caller pushes arguments and a comap key and calls the contract at version 1, callee checks the key, echoes its argument plus
+    /// one and ends with status 7
+    #[derive(Default)]
+    struct CallingVM {
+        result: Option<u64>,
+    }
+    impl VMHypervisor for CallingVM {
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError> {
+            if codata.context_count() > 1 {
+                //the caller's output comap is the input comap of the callee
+                assert_eq!(codata.peek_input_key(&[1]).unwrap(), vec![2]);
+                let argument = codata.pop_input_stack()?;
+                codata.push_output_stack(&[argument[0] + 1])?;
+                return Ok(VMResult::Ended(7));
+            }
+            match self.result {
+                None => {
+                    codata.push_output_key(&[1], &[2])?;
+                    codata.push_output_stack(&[41])?;
+                    call_test_contract(codata, 0, 1000)
+                }
+                Some(status) => {
+                    assert_eq!(status, 7);
+                    assert_eq!(codata.pop_input_stack().unwrap(), vec![42]);
+                    assert!(codata.pop_input_stack().is_err());
+                    Ok(VMResult::Ended(0))
+                }
+            }
+        }
+        fn set_result(&mut self, result: u64) {
+            self.result = Some(result);
+        }
+        fn set_error(&mut self, _code: u64) {
+            panic!("call should not fail");
+        }
+        fn enter_state(&mut self, _codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_call_contract() {
+        let mut codata = CoData::new();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut caller = ContractCaller::default();
+        let mut callsystem = CallSystem::default();
+        callsystem.global_storage = Some(RefCell::new(&mut db));
+        callsystem.add_call(CALL_CONTRACT_FEATURE, &mut caller).unwrap();
+
+        let vm = || -> Box<dyn VMHypervisor> { Box::from(CallingVM::default()) };
+        let mut vmm = VMManager::default();
+        vmm.vm_builders.insert(1, vm);
+
+        let mut context = ExecutionContext::default();
+        context.permissions = ContextPermissions::mutable_call();
+        context.self_address.version = 1;
+        codata.push_context(context).unwrap();
+
+        let mut manager = Manager::default();
+        let result = manager.execute(&mut codata, &callsystem, &vmm).unwrap();
+        assert_eq!(result.status, 0);
+        assert_eq!(codata.context_count(), 0);
+    }
+
+    /// Gets the next deploy address and then deploys, the deployed contract checks its code and data and outputs its own address
+    #[derive(Default)]
+    struct DeployingVM {
+        address: Option<Vec<u8>>,
+        result: Option<u64>,
+    }
+    impl VMHypervisor for DeployingVM {
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError> {
+            if codata.context_count() > 1 {
+                assert_eq!(codata.current_context().execution_type, ExecutionType::Deploy);
+                codata.push_output_stack(&codata.current_context().self_address.decode())?;
+                return Ok(VMResult::Ended(3));
+            }
+            if self.result.is_none() {
+                return Ok(VMResult::ElementCall(
+                    CALL_CONTRACT_FEATURE,
+                    CallContractFunctions::GetDeployAddress as u32,
+                ));
+            }
+            if self.address.is_none() {
+                self.address = Some(codata.pop_input_stack()?);
+                codata.push_output_key("!.c".as_bytes(), &[1, 2])?;
+                codata.push_output_key("!.d".as_bytes(), &[3])?;
+                codata.push_output_stack(&0u64.to_le_bytes())?;
+                codata.push_output_stack(&1000u64.to_le_bytes())?;
+                return Ok(VMResult::ElementCall(
+                    CALL_CONTRACT_FEATURE,
+                    CallContractFunctions::DeployContract as u32,
+                ));
+            }
+            assert_eq!(self.result, Some(3));
+            assert_eq!(codata.pop_input_stack().unwrap(), self.address.clone().unwrap());
+            Ok(VMResult::Ended(0))
+        }
+        fn set_result(&mut self, result: u64) {
+            self.result = Some(result);
+        }
+        fn set_error(&mut self, _code: u64) {
+            panic!("call should not fail");
+        }
+        fn enter_state(&mut self, codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            //the code and data to deploy are read from the input comap, as the NARM hypervisor does
+            if codata.current_context().execution_type == ExecutionType::Deploy {
+                assert_eq!(codata.peek_input_key("!.c".as_bytes())?, vec![1, 2]);
+                assert_eq!(codata.peek_input_key("!.d".as_bytes())?, vec![3]);
+            }
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_deploy_contract() {
+        let mut codata = CoData::new();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut context = ExecutionContext::default();
+        context.permissions = ContextPermissions::mutable_call();
+        context.self_address.version = 1;
+        {
+            let mut caller = ContractCaller::default();
+            let mut callsystem = CallSystem::default();
+            callsystem.global_storage = Some(RefCell::new(&mut db));
+            callsystem.add_call(CALL_CONTRACT_FEATURE, &mut caller).unwrap();
+
+            let vm = || -> Box<dyn VMHypervisor> { Box::from(DeployingVM::default()) };
+            let mut vmm = VMManager::default();
+            vmm.vm_builders.insert(1, vm);
+
+            codata.push_context(context.clone()).unwrap();
+
+            let mut manager = Manager::default();
+            let result = manager.execute(&mut codata, &callsystem, &vmm).unwrap();
+            assert_eq!(result.status, 0);
+        }
+        //the nonce is used up by the deployment
+        assert_eq!(db.get_deploy_nonce(&mut codata, context.self_address).unwrap(), 1);
+    }
+
+    /// Calls a CallContract function directly as an element call of a contract at version 1 with the given permissions
+    /// The arguments of a deployment are given, using [5] as salt
+    fn deploy_syscall(
+        db: &mut MemoryGlobalState,
+        permissions: ContextPermissions,
+        function: CallContractFunctions,
+    ) -> Result<ElementResult, NeutronError> {
+        let mut codata = CoData::new();
+        let mut context = ExecutionContext::default();
+        context.permissions = permissions;
+        context.self_address.version = 1;
+        codata.push_context(context).unwrap();
+        codata.push_output_key("!.c".as_bytes(), &[1, 2]).unwrap();
+        codata.push_output_stack(&0u64.to_le_bytes()).unwrap();
+        codata.push_output_stack(&1000u64.to_le_bytes()).unwrap();
+        if function as u32 == CallContractFunctions::DeployContractSalted as u32 {
+            codata.push_output_stack(&[5]).unwrap();
+        }
+
+        let mut caller = ContractCaller::default();
+        let mut callsystem = CallSystem::default();
+        callsystem.global_storage = Some(RefCell::new(db));
+        callsystem.add_call(CALL_CONTRACT_FEATURE, &mut caller).unwrap();
+        codata.enter_element();
+        let result = callsystem.call(&mut codata, CALL_CONTRACT_FEATURE, function as u32);
+        codata.exit_element();
+        result
+    }
+
+    #[test]
+    fn test_deploy_permissions() {
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        for permissions in [ContextPermissions::immutable_call(), ContextPermissions::pure_call()].iter() {
+            for function in [CallContractFunctions::DeployContract, CallContractFunctions::DeployContractSalted].iter() {
+                assert_eq!(
+                    deploy_syscall(&mut db, *permissions, *function).err(),
+                    Some(Recoverable(RecoverableError::RequiresPermissionSelfMod))
+                );
+            }
+        }
+        //an isolated contract can modify itself, but not the state of the contract it would deploy
+        assert_eq!(
+            deploy_syscall(&mut db, ContextPermissions::isolated_call(), CallContractFunctions::DeployContract).err(),
+            Some(Recoverable(RecoverableError::RequiresPermissionExternalMod))
+        );
+        assert_eq!(
+            deploy_syscall(&mut db, ContextPermissions::pure_call(), CallContractFunctions::GetDeployAddress).err(),
+            Some(Recoverable(RecoverableError::RequiresPermissionSelfAccess))
+        );
+        //the nonce is not used up by the failed deployments
+        let mut sender = NeutronAddress::default();
+        sender.version = 1;
+        assert_eq!(db.get_deploy_nonce(&mut CoData::new(), sender).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_deploy_address_in_use() {
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut sender = NeutronAddress::default();
+        sender.version = 1;
+        db.write_key(&derive_contract_address(&sender, 0, 1), &[0x02, 0], &[1]).unwrap();
+        db.write_key(&derive_salted_contract_address(&sender, &[1, 2], &[5], 1), &[0x02, 0], &[1])
+            .unwrap();
+        for function in [CallContractFunctions::DeployContract, CallContractFunctions::DeployContractSalted].iter() {
+            assert_eq!(
+                deploy_syscall(&mut db, ContextPermissions::mutable_call(), *function).err(),
+                Some(Host(HostError::ContractAddressInUse))
+            );
+        }
+        assert_eq!(db.get_deploy_nonce(&mut CoData::new(), sender).unwrap(), 0);
+    }
+}
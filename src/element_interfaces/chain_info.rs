@@ -0,0 +1,263 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::interface::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use crate::AddressDecoding;
+use neutron_common::*;
+/*
+## Chain Info
+
+ID: 7
+
+Functions:
+
+* [0] Available()
+* [1] BlockHeight() -> (height: stack u32)
+* [2] BlockCreator() -> (creator: stack NeutronAddress)
+* [3] BlockDifficulty() -> (difficulty: stack u64)
+* [4] BlockGasLimit() -> (gas_limit: stack u64)
+* [5] PreviousBlockTime() -> (time: stack u64)
+* [6] PreviousBlockHash(index: stack u64) -> (hash: stack [u8; 32])
+* [7] PreviousBlockHashCount() -> (count: stack u32)
+* [8] TransactionGasFees() -> (fees: stack u64)
+* [9] TransactionFees() -> (fees: stack u64)
+* [10] TransactionInputCount() -> (count: stack u32)
+* [11] TransactionInput(index: stack u64) -> (sender: stack NeutronAddress, value: stack u64, state: stack bytes)
+* [12] TransactionOutputCount() -> (count: stack u32)
+* [13] TransactionOutput(index: stack u64) -> (sender: stack NeutronAddress, value: stack u64, state: stack bytes)
+
+Gives information about the block the current execution is part of, as given by the host in a BlockContext, and about the transaction
+the execution is part of, as given in a TransactionContext. All numbers are little endian, and multiple outputs are popped in the order listed.
+
+PreviousBlockHash index 0 is the previous block, index 1 is the block before that, and so on. Not all blockchains reveal the same number
+of previous block hashes, which is given by PreviousBlockHashCount. Requesting a hash beyond that fails with ItemDoesntExist, as does
+requesting a transaction input or output beyond the count of inputs or outputs.
+The current time is not available, as it can not be revealed by all blockchains due to determinism problems.
+*/
+
+pub const CHAIN_INFO_FEATURE: u32 = 7;
+
+#[derive(FromPrimitive)]
+pub enum ChainInfoFunctions {
+    Available = 0, //reserved??
+    BlockHeight = 1,
+    BlockCreator = 2,
+    BlockDifficulty = 3,
+    BlockGasLimit = 4,
+    PreviousBlockTime = 5,
+    PreviousBlockHash = 6,
+    PreviousBlockHashCount = 7,
+    TransactionGasFees = 8,
+    TransactionFees = 9,
+    TransactionInputCount = 10,
+    TransactionInput = 11,
+    TransactionOutputCount = 12,
+    TransactionOutput = 13,
+}
+
+/// Exposes the BlockContext of the block being executed, and the TransactionContext of the transaction being executed, to smart contracts
+#[derive(Default, Clone, Debug)]
+pub struct ChainInfo {
+    pub block: BlockContext,
+    pub transaction: TransactionContext,
+}
+
+impl ChainInfo {
+    pub fn new(block: BlockContext) -> ChainInfo {
+        ChainInfo {
+            block,
+            transaction: TransactionContext::default(),
+        }
+    }
+}
+
+impl ElementAPI for ChainInfo {
+    fn system_call(
+        &mut self,
+        _callsystem: &CallSystem,
+        codata: &mut CoData,
+        feature: u32,
+        function: u32,
+    ) -> Result<ElementResult, NeutronError> {
+        if feature != CHAIN_INFO_FEATURE {
+            return Ok(ElementResult::Result(0));
+        }
+        let f = num::FromPrimitive::from_u32(function);
+        if f.is_none() {
+            return Err(Recoverable(RecoverableError::InvalidSystemFunction));
+        }
+        let f = f.unwrap();
+        match f {
+            ChainInfoFunctions::Available => return Ok(ElementResult::Result(1)),
+            ChainInfoFunctions::BlockHeight => codata.push_output_stack(&self.block.height.to_le_bytes())?,
+            ChainInfoFunctions::BlockCreator => codata.push_output_stack(&self.block.creator.decode())?,
+            ChainInfoFunctions::BlockDifficulty => codata.push_output_stack(&self.block.difficulty.to_le_bytes())?,
+            ChainInfoFunctions::BlockGasLimit => codata.push_output_stack(&self.block.gas_limit.to_le_bytes())?,
+            ChainInfoFunctions::PreviousBlockTime => codata.push_output_stack(&self.block.previous_time.to_le_bytes())?,
+            ChainInfoFunctions::PreviousBlockHash => {
+                let index = codata.pop_input_stack_u64()?;
+                let hash = match self.block.previous_hashes.get(index as usize) {
+                    Some(h) => h,
+                    None => return Err(Recoverable(RecoverableError::ItemDoesntExist)),
+                };
+                codata.push_output_stack(hash)?;
+            }
+            ChainInfoFunctions::PreviousBlockHashCount => {
+                codata.push_output_stack(&(self.block.previous_hashes.len() as u32).to_le_bytes())?;
+            }
+            ChainInfoFunctions::TransactionGasFees => codata.push_output_stack(&self.transaction.total_gas_fees.to_le_bytes())?,
+            ChainInfoFunctions::TransactionFees => codata.push_output_stack(&self.transaction.total_fees.to_le_bytes())?,
+            ChainInfoFunctions::TransactionInputCount => {
+                codata.push_output_stack(&(self.transaction.inputs.len() as u32).to_le_bytes())?;
+            }
+            ChainInfoFunctions::TransactionInput => {
+                let index = codata.pop_input_stack_u64()?;
+                push_tx_item(codata, self.transaction.inputs.get(index as usize))?;
+            }
+            ChainInfoFunctions::TransactionOutputCount => {
+                codata.push_output_stack(&(self.transaction.outputs.len() as u32).to_le_bytes())?;
+            }
+            ChainInfoFunctions::TransactionOutput => {
+                let index = codata.pop_input_stack_u64()?;
+                push_tx_item(codata, self.transaction.outputs.get(index as usize))?;
+            }
+        }
+        Ok(ElementResult::Result(0))
+    }
+}
+
+/// Pushes the fields of a transaction input or output in reverse, so that they are popped in the documented order
+fn push_tx_item(codata: &mut CoData, item: Option<&TxItem>) -> Result<(), NeutronError> {
+    let item = match item {
+        Some(i) => i,
+        None => return Err(Recoverable(RecoverableError::ItemDoesntExist)),
+    };
+    codata.push_output_stack(&item.state)?;
+    codata.push_output_stack(&item.value.to_le_bytes())?;
+    codata.push_output_stack(&item.sender.decode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block() -> BlockContext {
+        let mut block = BlockContext::default();
+        block.creator.version = 2;
+        block.creator.data[0] = 9;
+        block.gas_limit = 1_000_000;
+        block.difficulty = 12345;
+        block.height = 100;
+        block.previous_time = 1600000000;
+        block.previous_hashes = vec![[1; 32], [2; 32]];
+        block
+    }
+
+    fn call(chaininfo: &mut ChainInfo, function: ChainInfoFunctions, inputs: &[&[u8]]) -> Result<Vec<u8>, NeutronError> {
+        let mut codata = CoData::new();
+        for input in inputs {
+            codata.push_output_stack(input).unwrap();
+        }
+        let callsystem = CallSystem::default();
+        codata.enter_element();
+        let result = chaininfo.system_call(&callsystem, &mut codata, CHAIN_INFO_FEATURE, function as u32);
+        codata.exit_element();
+        result?;
+        codata.pop_input_stack()
+    }
+
+    #[test]
+    fn test_block_info() {
+        let mut chaininfo = ChainInfo::new(block());
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::BlockHeight, &[]).unwrap(),
+            100u32.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::BlockCreator, &[]).unwrap(),
+            block().creator.decode()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::BlockDifficulty, &[]).unwrap(),
+            12345u64.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::BlockGasLimit, &[]).unwrap(),
+            1_000_000u64.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::PreviousBlockTime, &[]).unwrap(),
+            1600000000u64.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn test_previous_block_hashes() {
+        let mut chaininfo = ChainInfo::new(block());
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::PreviousBlockHashCount, &[]).unwrap(),
+            2u32.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::PreviousBlockHash, &[&0u64.to_le_bytes()]).unwrap(),
+            vec![1; 32]
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::PreviousBlockHash, &[&1u64.to_le_bytes()]).unwrap(),
+            vec![2; 32]
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::PreviousBlockHash, &[&2u64.to_le_bytes()]),
+            Err(Recoverable(RecoverableError::ItemDoesntExist))
+        );
+    }
+
+    #[test]
+    fn test_transaction_info() {
+        let mut chaininfo = ChainInfo::new(block());
+        chaininfo.transaction.total_gas_fees = 300;
+        chaininfo.transaction.total_fees = 350;
+        let mut item = TxItem::default();
+        item.sender.version = 2;
+        item.value = 1000;
+        item.state = vec![7, 7];
+        chaininfo.transaction.outputs.push(item.clone());
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::TransactionGasFees, &[]).unwrap(),
+            300u64.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::TransactionFees, &[]).unwrap(),
+            350u64.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::TransactionInputCount, &[]).unwrap(),
+            0u32.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::TransactionOutputCount, &[]).unwrap(),
+            1u32.to_le_bytes()
+        );
+        assert_eq!(
+            call(&mut chaininfo, ChainInfoFunctions::TransactionInput, &[&0u64.to_le_bytes()]),
+            Err(Recoverable(RecoverableError::ItemDoesntExist))
+        );
+
+        let mut codata = CoData::new();
+        codata.push_output_stack(&0u64.to_le_bytes()).unwrap();
+        codata.enter_element();
+        chaininfo
+            .system_call(
+                &CallSystem::default(),
+                &mut codata,
+                CHAIN_INFO_FEATURE,
+                ChainInfoFunctions::TransactionOutput as u32,
+            )
+            .unwrap();
+        codata.exit_element();
+        assert_eq!(codata.pop_input_stack_address().unwrap(), item.sender);
+        assert_eq!(codata.pop_input_stack_u64().unwrap(), 1000);
+        assert_eq!(codata.pop_input_stack().unwrap(), vec![7, 7]);
+    }
+}
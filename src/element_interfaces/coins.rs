@@ -0,0 +1,175 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use neutron_common::*;
+/*
+## Coins
+
+ID: 8
+
+Functions:
+
+* [0] Available()
+* [1] Balance() -> (balance: stack u64)
+* [2] BalanceOf(address: stack NeutronAddress) -> (balance: stack u64)
+* [3] Transfer(address: stack NeutronAddress, value: stack u64) -> ()
+
+Gives access to the native coins of the blockchain. Balances are kept by the host in protected storage.
+
+Coins sent with an execution (its value_sent) are moved from the sender to the executed contract as the execution begins, and
+are moved back if the execution fails. Sending coins with a nested call requires the caller to be able to modify external state.
+
+Balance gives the balance of the current contract and requires self access. BalanceOf gives the balance of any address and
+requires external access. Transfer sends coins from the current contract to any address, without executing any code at that
+address, and requires external modification. It fails with LowTokenBalance if the contract does not hold enough coins.
+*/
+
+pub const COINS_FEATURE: u32 = 8;
+
+#[derive(FromPrimitive)]
+pub enum CoinsFunctions {
+    Available = 0, //reserved??
+    Balance = 1,
+    BalanceOf = 2,
+    Transfer = 3,
+}
+
+#[derive(Default)]
+pub struct Coins {}
+
+impl ElementAPI for Coins {
+    fn system_call(
+        &mut self,
+        callsystem: &CallSystem,
+        codata: &mut CoData,
+        feature: u32,
+        function: u32,
+    ) -> Result<ElementResult, NeutronError> {
+        if feature != COINS_FEATURE {
+            return Ok(ElementResult::Result(0));
+        }
+        let f = num::FromPrimitive::from_u32(function);
+        if f.is_none() {
+            return Err(Recoverable(RecoverableError::InvalidSystemFunction));
+        }
+        let f = f.unwrap();
+        let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+        match f {
+            CoinsFunctions::Available => return Ok(ElementResult::Result(1)),
+            CoinsFunctions::Balance => {
+                codata.permissions().assert_has_self_access()?;
+                let address = codata.current_context().self_address;
+                let balance = storage.get_balance(codata, address)?;
+                codata.push_output_stack(&balance.to_le_bytes())?;
+            }
+            CoinsFunctions::BalanceOf => {
+                codata.permissions().assert_has_external_access()?;
+                let address = codata.pop_input_stack_address()?;
+                let balance = storage.get_balance(codata, address)?;
+                codata.push_output_stack(&balance.to_le_bytes())?;
+            }
+            CoinsFunctions::Transfer => {
+                codata.permissions().assert_has_external_modification()?;
+                let address = codata.pop_input_stack_address()?;
+                let value = codata.pop_input_stack_u64()?;
+                let from = codata.current_context().self_address;
+                storage.transfer_coins(codata, from, address, value)?;
+            }
+        }
+        Ok(ElementResult::Result(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryGlobalState;
+    use crate::element_interfaces::storage::*;
+    use crate::interface::*;
+    use crate::test_helpers::*;
+    use crate::AddressDecoding;
+    use std::cell::RefCell;
+
+    /// Calls the Coins element from a contract at address 1 holding 100 coins, returning the result and the balances of addresses 1 and 2
+    fn call(
+        function: CoinsFunctions,
+        permissions: ContextPermissions,
+        inputs: &[Vec<u8>],
+    ) -> (Result<Vec<Vec<u8>>, NeutronError>, u64, u64) {
+        let mut codata = CoData::new();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        db.set_balance(&mut codata, address(1), 100).unwrap();
+        let mut context = ExecutionContext::default();
+        context.self_address = address(1);
+        context.permissions = permissions;
+        codata.push_context(context).unwrap();
+        for input in inputs {
+            codata.push_output_stack(input).unwrap();
+        }
+
+        let mut coins = Coins::default();
+        let result = {
+            let mut callsystem = CallSystem::default();
+            callsystem.global_storage = Some(RefCell::new(&mut db));
+            callsystem.add_call(COINS_FEATURE, &mut coins).unwrap();
+            codata.enter_element();
+            let result = callsystem.call(&mut codata, COINS_FEATURE, function as u32);
+            codata.exit_element();
+            result
+        };
+        let result = result.map(|_| codata.input_stack().clone());
+        (
+            result,
+            db.get_balance(&mut codata, address(1)).unwrap(),
+            db.get_balance(&mut codata, address(2)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_balance() {
+        let (result, _, _) = call(CoinsFunctions::Balance, ContextPermissions::immutable_call(), &[]);
+        assert_eq!(result.unwrap(), vec![100u64.to_le_bytes().to_vec()]);
+        let (result, _, _) = call(CoinsFunctions::Balance, ContextPermissions::pure_call(), &[]);
+        assert_eq!(result.unwrap_err(), Recoverable(RecoverableError::RequiresPermissionSelfAccess));
+    }
+
+    #[test]
+    fn test_balance_of() {
+        let (result, _, _) = call(
+            CoinsFunctions::BalanceOf,
+            ContextPermissions::immutable_call(),
+            &[address(1).decode()],
+        );
+        assert_eq!(result.unwrap(), vec![100u64.to_le_bytes().to_vec()]);
+        let (result, _, _) = call(
+            CoinsFunctions::BalanceOf,
+            ContextPermissions::immutable_call(),
+            &[address(2).decode()],
+        );
+        assert_eq!(result.unwrap(), vec![0u64.to_le_bytes().to_vec()]);
+        let (result, _, _) = call(
+            CoinsFunctions::BalanceOf,
+            ContextPermissions::isolated_call(),
+            &[address(2).decode()],
+        );
+        assert_eq!(result.unwrap_err(), Recoverable(RecoverableError::RequiresPermissionExternalAccess));
+    }
+
+    #[test]
+    fn test_transfer() {
+        let inputs = |value: u64| vec![value.to_le_bytes().to_vec(), address(2).decode()];
+        let (result, from, to) = call(CoinsFunctions::Transfer, ContextPermissions::mutable_call(), &inputs(30));
+        assert!(result.is_ok());
+        assert_eq!((from, to), (70, 30));
+
+        let (result, from, to) = call(CoinsFunctions::Transfer, ContextPermissions::mutable_call(), &inputs(101));
+        assert_eq!(result.unwrap_err(), Recoverable(RecoverableError::LowTokenBalance));
+        assert_eq!((from, to), (100, 0));
+
+        let (result, from, _) = call(CoinsFunctions::Transfer, ContextPermissions::isolated_call(), &inputs(30));
+        assert_eq!(result.unwrap_err(), Recoverable(RecoverableError::RequiresPermissionExternalMod));
+        assert_eq!(from, 100);
+    }
+}
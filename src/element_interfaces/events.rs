@@ -0,0 +1,203 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use crate::AddressDecoding;
+use neutron_common::*;
+use ring::digest::{digest, SHA256};
+use std::fmt;
+/*
+## Events
+
+ID: 9
+
+Functions:
+
+* [0] Available()
+* [1] EmitEvent(data: stack bytes, ...topics: stack bytes) -> ()
+
+EmitEvent records an event of the current contract, with the ABI data and indexed topics given. All items left on the costack
+after the data are used as the topics of the event, the first being the item on top of the costack. An event can have at most
+MAX_EVENT_TOPICS topics. Emitting events requires self modification.
+
+Unlike log messages, events are part of the result of an execution. Events are recorded along with the state changes of the
+context which emitted them, and are discarded when that context reverts. The result of an execution includes a bloom filter of the
+addresses and topics of its events, so that events can be searched for without going through all results.
+*/
+
+pub const EVENTS_FEATURE: u32 = 9;
+
+/// The maximum number of indexed topics of an event
+pub const MAX_EVENT_TOPICS: usize = 4;
+
+#[derive(FromPrimitive)]
+pub enum EventsFunctions {
+    Available = 0, //reserved??
+    EmitEvent = 1,
+}
+
+/// An event emitted during execution, as collected into the CoData
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Event {
+    /// The address of the contract which emitted the event
+    pub address: NeutronAddress,
+    pub topics: Vec<Vec<u8>>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct EventEmitter {}
+
+impl ElementAPI for EventEmitter {
+    fn system_call(
+        &mut self,
+        _callsystem: &CallSystem,
+        codata: &mut CoData,
+        feature: u32,
+        function: u32,
+    ) -> Result<ElementResult, NeutronError> {
+        if feature != EVENTS_FEATURE {
+            return Ok(ElementResult::Result(0));
+        }
+        let f = num::FromPrimitive::from_u32(function);
+        if f.is_none() {
+            return Err(Recoverable(RecoverableError::InvalidSystemFunction));
+        }
+        let f = f.unwrap();
+        match f {
+            EventsFunctions::Available => Ok(ElementResult::Result(1)),
+            EventsFunctions::EmitEvent => {
+                codata.permissions().assert_has_self_modification()?;
+                let data = codata.pop_input_stack()?;
+                let mut topics = vec![];
+                while let Ok(topic) = codata.pop_input_stack() {
+                    topics.push(topic);
+                }
+                if topics.len() > MAX_EVENT_TOPICS {
+                    return Err(Host(HostError::TooManyEventTopics));
+                }
+                let address = codata.current_context().self_address;
+                codata.events.push(Event { address, topics, data });
+                Ok(ElementResult::Result(0))
+            }
+        }
+    }
+}
+
+const BLOOM_BYTES: usize = 256;
+
+/// A 2048 bit bloom filter of the addresses and topics of events
+/// Each item sets 3 bits, chosen by the first 6 bytes of its SHA-256 hash
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Bloom(pub [u8; BLOOM_BYTES]);
+
+impl Default for Bloom {
+    fn default() -> Bloom {
+        Bloom([0; BLOOM_BYTES])
+    }
+}
+
+impl fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl Bloom {
+    /// Builds the bloom filter of the given events
+    pub fn from_events(events: &[Event]) -> Bloom {
+        let mut bloom = Bloom::default();
+        for event in events {
+            bloom.accrue(&event.address.decode());
+            for topic in event.topics.iter() {
+                bloom.accrue(topic);
+            }
+        }
+        bloom
+    }
+    fn bits(item: &[u8]) -> [usize; 3] {
+        let hash = digest(&SHA256, item);
+        let h = hash.as_ref();
+        let mut bits = [0; 3];
+        for i in 0..3 {
+            bits[i] = ((h[i * 2] as usize) << 8 | h[i * 2 + 1] as usize) % (BLOOM_BYTES * 8);
+        }
+        bits
+    }
+    /// Adds an item (an encoded address or a topic) to the filter
+    pub fn accrue(&mut self, item: &[u8]) {
+        for bit in Bloom::bits(item).iter() {
+            self.0[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    /// Adds all items of another filter to this filter
+    pub fn accrue_bloom(&mut self, other: &Bloom) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a |= b;
+        }
+    }
+    /// Checks if the item may have been added to the filter. False positives are possible, false negatives are not
+    pub fn contains(&self, item: &[u8]) -> bool {
+        Bloom::bits(item).iter().all(|bit| self.0[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::*;
+
+    fn emit(permissions: ContextPermissions, inputs: &[&[u8]]) -> (Result<ElementResult, NeutronError>, Vec<Event>) {
+        let mut codata = CoData::new();
+        let mut context = ExecutionContext::default();
+        context.self_address.version = 2;
+        context.permissions = permissions;
+        codata.push_context(context).unwrap();
+        for input in inputs {
+            codata.push_output_stack(input).unwrap();
+        }
+        let callsystem = CallSystem::default();
+        codata.enter_element();
+        let result = EventEmitter::default().system_call(&callsystem, &mut codata, EVENTS_FEATURE, EventsFunctions::EmitEvent as u32);
+        codata.exit_element();
+        (result, codata.events)
+    }
+
+    #[test]
+    fn test_emit_event() {
+        let (result, events) = emit(ContextPermissions::mutable_call(), &[&[2], &[1], &[5, 5]]);
+        assert!(result.is_ok());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].address.version, 2);
+        assert_eq!(events[0].data, vec![5, 5]);
+        assert_eq!(events[0].topics, vec![vec![1], vec![2]]);
+
+        let (result, events) = emit(ContextPermissions::immutable_call(), &[&[1], &[5, 5]]);
+        assert_eq!(result.err(), Some(Recoverable(RecoverableError::RequiresPermissionSelfMod)));
+        assert!(events.is_empty());
+
+        let topics = [&[1u8][..]; MAX_EVENT_TOPICS + 2];
+        let (result, events) = emit(ContextPermissions::mutable_call(), &topics);
+        assert_eq!(result.err(), Some(Host(HostError::TooManyEventTopics)));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_bloom() {
+        let mut event = Event::default();
+        event.topics = vec![b"Transfer".to_vec(), vec![1; 32]];
+        let bloom = Bloom::from_events(&[event.clone()]);
+        assert!(bloom.contains(b"Transfer"));
+        assert!(bloom.contains(&[1; 32]));
+        assert!(bloom.contains(&event.address.decode()));
+        assert!(!bloom.contains(b"Approval"));
+
+        let mut combined = Bloom::default();
+        assert!(!combined.contains(b"Transfer"));
+        combined.accrue_bloom(&bloom);
+        assert_eq!(combined, bloom);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use neutron_common::*;
+use ring::digest::{digest, SHA256, SHA512};
+use std::collections::HashMap;
+/*
+## Hashing
+
+ID: 10
+
+Functions:
+
+* [0] Available()
+* [1] Sha256(data: stack bytes) -> (hash: stack [u8; 32])
+* [2] Sha512(data: stack bytes) -> (hash: stack [u8; 64])
+* [3] Hash160(data: stack bytes) -> (hash: stack [u8; 20])
+
+Computes cryptographic hashes of data given on the costack, which is far cheaper than hashing within the VM. Hash160 is the
+Bitcoin style RIPEMD-160 hash of the SHA-256 hash of the data.
+
+No permissions are required. Hashing is charged per byte of data through the ELEMENT_COST_INPUT_COSTACK_BYTE parameter of the gas
+schedule, like any other element function. Unlike most element functions, hashing is not free in the default gas schedule, where each
+hash costs DEFAULT_HASHING_BASE_COST plus DEFAULT_HASHING_BYTE_COST per byte of data.
+*/
+
+pub const HASHING_FEATURE: u32 = 10;
+
+/// The flat cost of each hashing function in the default gas schedule
+pub const DEFAULT_HASHING_BASE_COST: u64 = 20;
+/// The cost per byte of data hashed in the default gas schedule
+pub const DEFAULT_HASHING_BYTE_COST: u64 = 1;
+
+#[derive(FromPrimitive, Clone, Copy)]
+pub enum HashingFunctions {
+    Available = 0, //reserved??
+    Sha256 = 1,
+    Sha512 = 2,
+    Hash160 = 3,
+}
+
+#[derive(Default)]
+pub struct Hasher {}
+
+impl ElementAPI for Hasher {
+    fn system_call(
+        &mut self,
+        _callsystem: &CallSystem,
+        codata: &mut CoData,
+        feature: u32,
+        function: u32,
+    ) -> Result<ElementResult, NeutronError> {
+        if feature != HASHING_FEATURE {
+            return Ok(ElementResult::Result(0));
+        }
+        let f = num::FromPrimitive::from_u32(function);
+        if f.is_none() {
+            return Err(Recoverable(RecoverableError::InvalidSystemFunction));
+        }
+        let f = f.unwrap();
+        let hash = match f {
+            HashingFunctions::Available => return Ok(ElementResult::Result(1)),
+            HashingFunctions::Sha256 => digest(&SHA256, &codata.pop_input_stack()?).as_ref().to_vec(),
+            HashingFunctions::Sha512 => digest(&SHA512, &codata.pop_input_stack()?).as_ref().to_vec(),
+            HashingFunctions::Hash160 => hash160(&codata.pop_input_stack()?).to_vec(),
+        };
+        codata.push_output_stack(&hash)?;
+        Ok(ElementResult::Result(0))
+    }
+}
+
+/// The costs of the hashing functions in the default gas schedule, indexed by the ELEMENT_COST_* parameters
+pub fn default_hashing_costs() -> HashMap<u32, Vec<u64>> {
+    let mut costs = HashMap::new();
+    for function in &[HashingFunctions::Sha256, HashingFunctions::Sha512, HashingFunctions::Hash160] {
+        let mut parameters = vec![0; ELEMENT_COST_INPUT_COSTACK_BYTE + 1];
+        parameters[ELEMENT_COST_BASE] = DEFAULT_HASHING_BASE_COST;
+        parameters[ELEMENT_COST_INPUT_COSTACK_BYTE] = DEFAULT_HASHING_BYTE_COST;
+        costs.insert(*function as u32, parameters);
+    }
+    costs
+}
+
+/// The RIPEMD-160 hash of the SHA-256 hash of the data
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    ripemd160(digest(&SHA256, data).as_ref())
+}
+
+//RIPEMD-160 is not provided by ring. The message word used by each step of the left and right lines
+const RIPEMD_WORDS_LEFT: [usize; 80] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 7, 4, 13, 1, 10, 6, 15, 3, 12, 0, 9, 5, 2, 14, 11, 8, 3, 10, 14, 4, 9, 15, 8, 1,
+    2, 7, 0, 6, 13, 11, 5, 12, 1, 9, 11, 10, 0, 8, 12, 4, 13, 3, 7, 15, 14, 5, 6, 2, 4, 0, 5, 9, 7, 12, 2, 10, 14, 1, 3, 8, 11, 6, 15, 13,
+];
+const RIPEMD_WORDS_RIGHT: [usize; 80] = [
+    5, 14, 7, 0, 9, 2, 11, 4, 13, 6, 15, 8, 1, 10, 3, 12, 6, 11, 3, 7, 0, 13, 5, 10, 14, 15, 8, 12, 4, 9, 1, 2, 15, 5, 1, 3, 7, 14, 6, 9,
+    11, 8, 12, 2, 10, 0, 4, 13, 8, 6, 4, 1, 3, 11, 15, 0, 5, 12, 2, 13, 9, 7, 10, 14, 12, 15, 10, 4, 1, 5, 8, 7, 6, 2, 13, 14, 0, 3, 9, 11,
+];
+//The rotation of each step of the left and right lines
+const RIPEMD_SHIFTS_LEFT: [u32; 80] = [
+    11, 14, 15, 12, 5, 8, 7, 9, 11, 13, 14, 15, 6, 7, 9, 8, 7, 6, 8, 13, 11, 9, 7, 15, 7, 12, 15, 9, 11, 7, 13, 12, 11, 13, 6, 7, 14, 9,
+    13, 15, 14, 8, 13, 6, 5, 12, 7, 5, 11, 12, 14, 15, 14, 15, 9, 8, 9, 14, 5, 6, 8, 6, 5, 12, 9, 15, 5, 11, 6, 8, 13, 12, 5, 12, 13, 14,
+    11, 8, 5, 6,
+];
+const RIPEMD_SHIFTS_RIGHT: [u32; 80] = [
+    8, 9, 9, 11, 13, 15, 15, 5, 7, 7, 8, 11, 14, 14, 12, 6, 9, 13, 15, 7, 12, 8, 9, 11, 7, 7, 12, 7, 6, 15, 13, 11, 9, 7, 15, 11, 8, 6, 6,
+    14, 12, 13, 5, 14, 13, 13, 7, 5, 15, 5, 8, 11, 14, 14, 6, 14, 6, 9, 12, 9, 12, 5, 15, 8, 8, 5, 12, 9, 12, 5, 14, 6, 8, 13, 6, 5, 15,
+    13, 11, 11,
+];
+//The constant added in each round of the left and right lines
+const RIPEMD_CONSTANTS_LEFT: [u32; 5] = [0x0000_0000, 0x5a82_7999, 0x6ed9_eba1, 0x8f1b_bcdc, 0xa953_fd4e];
+const RIPEMD_CONSTANTS_RIGHT: [u32; 5] = [0x50a2_8be6, 0x5c4d_d124, 0x6d70_3ef3, 0x7a6d_76e9, 0x0000_0000];
+
+fn ripemd_function(round: usize, x: u32, y: u32, z: u32) -> u32 {
+    match round {
+        0 => x ^ y ^ z,
+        1 => (x & y) | (!x & z),
+        2 => (x | !y) ^ z,
+        3 => (x & z) | (y & !z),
+        _ => x ^ (y | !z),
+    }
+}
+
+fn ripemd_compress(state: &mut [u32; 5], block: &[u8]) {
+    let mut words = [0u32; 16];
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = u32::from_le_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+    }
+    let mut left = *state;
+    let mut right = *state;
+    for step in 0..80 {
+        let round = step / 16;
+        let t = left[0]
+            .wrapping_add(ripemd_function(round, left[1], left[2], left[3]))
+            .wrapping_add(words[RIPEMD_WORDS_LEFT[step]])
+            .wrapping_add(RIPEMD_CONSTANTS_LEFT[round])
+            .rotate_left(RIPEMD_SHIFTS_LEFT[step])
+            .wrapping_add(left[4]);
+        left = [left[4], t, left[1], left[2].rotate_left(10), left[3]];
+        let t = right[0]
+            .wrapping_add(ripemd_function(4 - round, right[1], right[2], right[3]))
+            .wrapping_add(words[RIPEMD_WORDS_RIGHT[step]])
+            .wrapping_add(RIPEMD_CONSTANTS_RIGHT[round])
+            .rotate_left(RIPEMD_SHIFTS_RIGHT[step])
+            .wrapping_add(right[4]);
+        right = [right[4], t, right[1], right[2].rotate_left(10), right[3]];
+    }
+    let t = state[1].wrapping_add(left[2]).wrapping_add(right[3]);
+    state[1] = state[2].wrapping_add(left[3]).wrapping_add(right[4]);
+    state[2] = state[3].wrapping_add(left[4]).wrapping_add(right[0]);
+    state[3] = state[4].wrapping_add(left[0]).wrapping_add(right[1]);
+    state[4] = state[0].wrapping_add(left[1]).wrapping_add(right[2]);
+    state[0] = t;
+}
+
+/// The RIPEMD-160 hash of the data
+pub fn ripemd160(data: &[u8]) -> [u8; 20] {
+    let mut state = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    //the data is padded with a single 1 bit, then zeros, then the bit length of the data, up to a multiple of 64 bytes
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+    for block in padded.chunks(64) {
+        ripemd_compress(&mut state, block);
+    }
+    let mut hash = [0; 20];
+    for (i, word) in state.iter().enumerate() {
+        hash[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::*;
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn hash(function: HashingFunctions, data: &[u8]) -> Vec<u8> {
+        let mut codata = CoData::new();
+        codata.push_context(ExecutionContext::default()).unwrap();
+        codata.push_output_stack(data).unwrap();
+        let callsystem = CallSystem::default();
+        codata.enter_element();
+        Hasher::default()
+            .system_call(&callsystem, &mut codata, HASHING_FEATURE, function as u32)
+            .unwrap();
+        codata.exit_element();
+        assert_eq!(codata.input_stack().len(), 1);
+        codata.pop_input_stack().unwrap()
+    }
+
+    #[test]
+    fn test_ripemd160() {
+        assert_eq!(hex(&ripemd160(b"")), "9c1185a5c5e9fc54612808977ee8f548b2258d31");
+        assert_eq!(hex(&ripemd160(b"abc")), "8eb208f7e05d987a9b044a8e98c6b087f15a0bfc");
+        assert_eq!(hex(&ripemd160(b"message digest")), "5d0689ef49d2fae572b881b123a85ffa21595f36");
+        assert_eq!(
+            hex(&ripemd160(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "9b752e45573d4b39f4dbd3323cab82bf63326bfb"
+        );
+        assert_eq!(hex(&ripemd160(&[b'a'; 1_000_000])), "52783243c1697bdbe16d37f97f68f08325dc1528");
+    }
+
+    #[test]
+    fn test_hash_functions() {
+        assert_eq!(
+            hex(&hash(HashingFunctions::Sha256, b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&hash(HashingFunctions::Sha512, b"abc")),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(
+            hex(&hash(HashingFunctions::Hash160, b"")),
+            "b472a266d0bd89c13706a4132ccfb16f7c3b9fcb"
+        );
+    }
+
+    #[test]
+    fn test_hash_charges_per_byte() {
+        let mut hasher = Hasher::default();
+        let mut callsystem = CallSystem::default();
+        callsystem.add_call(HASHING_FEATURE, &mut hasher).unwrap();
+        let mut codata = CoData::new();
+        let mut functions = HashMap::new();
+        functions.insert(HashingFunctions::Sha256 as u32, vec![50, 2]);
+        codata.gas_schedule.element_costs.insert(HASHING_FEATURE, functions);
+        codata.gas_remaining = 1000;
+        codata.push_output_stack(&[0; 100]).unwrap();
+        codata.flip_stacks();
+        callsystem
+            .call(&mut codata, HASHING_FEATURE, HashingFunctions::Sha256 as u32)
+            .unwrap();
+        assert_eq!(codata.gas_remaining, 1000 - 50 - 200);
+    }
+
+    #[test]
+    fn test_default_hash_costs() {
+        let mut hasher = Hasher::default();
+        let mut callsystem = CallSystem::default();
+        callsystem.add_call(HASHING_FEATURE, &mut hasher).unwrap();
+        let mut codata = CoData::new();
+        codata.gas_remaining = 1000;
+        codata.push_output_stack(&[0; 100]).unwrap();
+        codata.flip_stacks();
+        callsystem
+            .call(&mut codata, HASHING_FEATURE, HashingFunctions::Hash160 as u32)
+            .unwrap();
+        assert_eq!(
+            codata.gas_remaining,
+            1000 - DEFAULT_HASHING_BASE_COST - 100 * DEFAULT_HASHING_BYTE_COST
+        );
+        //checking if hashing is available stays free
+        callsystem
+            .call(&mut codata, HASHING_FEATURE, HashingFunctions::Available as u32)
+            .unwrap();
+        assert_eq!(
+            codata.gas_remaining,
+            1000 - DEFAULT_HASHING_BASE_COST - 100 * DEFAULT_HASHING_BYTE_COST
+        );
+    }
+}
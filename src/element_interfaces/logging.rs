@@ -26,7 +26,7 @@ Note in neutron-star, log_info is used by default for println!
 
 pub const LOGGING_FEATURE: u32 = 4;
 
-#[derive(FromPrimitive)]
+#[derive(FromPrimitive, Clone, Copy, Debug, Eq, PartialEq)]
 pub enum LoggingFunctions{
     Available = 0, //reserved??
     LogDebug = 1,
@@ -35,6 +35,13 @@ pub enum LoggingFunctions{
     LogError
 }
 
+/// A log message emitted during execution, as collected into the CoData
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogMessage{
+    pub level: LoggingFunctions,
+    pub message: String
+}
+
 impl <'a>ElementAPI for (dyn LoggingInterface + 'a){
     fn system_call(&mut self, _callsystem: &CallSystem, codata: &mut CoData, feature: u32, function: u32) -> Result<ElementResult, NeutronError>{
         self.try_syscall(codata, feature, function)
@@ -52,6 +59,11 @@ pub trait LoggingInterface{
         }
         let f=f.unwrap();
         let message = compile_log_message(stack)?;
+        //messages are always collected, regardless of how (or if) the interface prints them
+        stack.logs.push(LogMessage{
+            level: f,
+            message: message.clone()
+        });
         let result = match f{
             LoggingFunctions::LogDebug => {
                 self.log_debug(stack, message)
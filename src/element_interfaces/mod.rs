@@ -1,3 +1,10 @@
 pub mod storage;
 pub mod logging;
-pub mod debug_data;
\ No newline at end of file
+pub mod debug_data;
+pub mod call_contract;
+pub mod upgrade;
+pub mod chain_info;
+pub mod coins;
+pub mod events;
+pub mod hashing;
+pub mod signatures;
\ No newline at end of file
@@ -0,0 +1,121 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use neutron_common::*;
+use ring::signature::{UnparsedPublicKey, VerificationAlgorithm, ECDSA_P256_SHA256_FIXED, ED25519};
+/*
+## Signatures
+
+ID: 11
+
+Functions:
+
+* [0] Available()
+* [1] VerifyEd25519(public_key: stack [u8; 32], signature: stack [u8; 64], message: stack bytes) -> result code
+* [2] VerifyEcdsaP256(public_key: stack [u8; 65], signature: stack [u8; 64], message: stack bytes) -> result code
+
+Verifies signatures of a message, returning a result code of 1 if the signature is valid and 0 if it is not. A malformed public
+key or signature is treated as an invalid signature rather than an error.
+
+VerifyEcdsaP256 expects an uncompressed SEC1 public key and a fixed length signature (r followed by s, both big endian) of the
+SHA-256 hash of the message.
+
+No permissions are required. Verification is charged through the gas schedule, like any other element function.
+*/
+
+pub const SIGNATURES_FEATURE: u32 = 11;
+
+#[derive(FromPrimitive, Clone, Copy)]
+pub enum SignaturesFunctions {
+    Available = 0, //reserved??
+    VerifyEd25519 = 1,
+    VerifyEcdsaP256 = 2,
+}
+
+#[derive(Default)]
+pub struct SignatureVerifier {}
+
+impl ElementAPI for SignatureVerifier {
+    fn system_call(
+        &mut self,
+        _callsystem: &CallSystem,
+        codata: &mut CoData,
+        feature: u32,
+        function: u32,
+    ) -> Result<ElementResult, NeutronError> {
+        if feature != SIGNATURES_FEATURE {
+            return Ok(ElementResult::Result(0));
+        }
+        let f = num::FromPrimitive::from_u32(function);
+        if f.is_none() {
+            return Err(Recoverable(RecoverableError::InvalidSystemFunction));
+        }
+        let f = f.unwrap();
+        let valid = match f {
+            SignaturesFunctions::Available => return Ok(ElementResult::Result(1)),
+            SignaturesFunctions::VerifyEd25519 => verify(codata, &ED25519)?,
+            SignaturesFunctions::VerifyEcdsaP256 => verify(codata, &ECDSA_P256_SHA256_FIXED)?,
+        };
+        Ok(ElementResult::Result(valid as u64))
+    }
+}
+
+fn verify(codata: &mut CoData, algorithm: &'static dyn VerificationAlgorithm) -> Result<bool, NeutronError> {
+    let public_key = codata.pop_input_stack()?;
+    let signature = codata.pop_input_stack()?;
+    let message = codata.pop_input_stack()?;
+    Ok(UnparsedPublicKey::new(algorithm, public_key).verify(&message, &signature).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    fn call_verify(function: SignaturesFunctions, public_key: &[u8], signature: &[u8], message: &[u8]) -> u64 {
+        let mut codata = CoData::new();
+        codata.push_context(ExecutionContext::default()).unwrap();
+        codata.push_output_stack(message).unwrap();
+        codata.push_output_stack(signature).unwrap();
+        codata.push_output_stack(public_key).unwrap();
+        let callsystem = CallSystem::default();
+        codata.enter_element();
+        let result = SignatureVerifier::default().system_call(&callsystem, &mut codata, SIGNATURES_FEATURE, function as u32);
+        codata.exit_element();
+        match result.unwrap() {
+            ElementResult::Result(r) => r,
+            _ => panic!("unexpected element result"),
+        }
+    }
+
+    #[test]
+    fn test_verify_ed25519() {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let public_key = key.public_key().as_ref();
+        let signature = key.sign(b"hello");
+        let f = SignaturesFunctions::VerifyEd25519;
+        assert_eq!(call_verify(f, public_key, signature.as_ref(), b"hello"), 1);
+        assert_eq!(call_verify(f, public_key, signature.as_ref(), b"hellp"), 0);
+        assert_eq!(call_verify(f, public_key, &signature.as_ref()[1..], b"hello"), 0);
+        assert_eq!(call_verify(f, &[1, 2, 3], signature.as_ref(), b"hello"), 0);
+    }
+
+    #[test]
+    fn test_verify_ecdsa_p256() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        let public_key = key.public_key().as_ref();
+        let signature = key.sign(&rng, b"hello").unwrap();
+        let f = SignaturesFunctions::VerifyEcdsaP256;
+        assert_eq!(call_verify(f, public_key, signature.as_ref(), b"hello"), 1);
+        assert_eq!(call_verify(f, public_key, signature.as_ref(), b"hellp"), 0);
+        //an Ed25519 signature is not accepted as an ECDSA signature
+        let ed25519 = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let signature = ed25519.sign(b"hello");
+        assert_eq!(call_verify(f, ed25519.public_key().as_ref(), signature.as_ref(), b"hello"), 0);
+    }
+}
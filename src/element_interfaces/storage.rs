@@ -4,6 +4,7 @@ use crate::neutronerror::NeutronError::*;
 use crate::callsystem::*;
 use neutron_common::*;
 use std::convert::*;
+use std::collections::HashMap;
 /*
 ## Global Storage
 
@@ -14,16 +15,43 @@ Functions:
 * store_state(key, value) -> ()
 * load_state(key) -> (value)
 * key_exists(key) -> (bool)
+* create_token_transfer(owner: NeutronAddress, id: u64, value: u64) -> ()
+* claim_token_transfer(owner: NeutronAddress, id: u64) -> (value: u64)
+* get_token_balance(owner: NeutronAddress, id: u64, address: NeutronAddress) -> (balance: u64)
+
+Tokens are identified by the address of the contract which owns them and an id, and balances are kept in protected storage of the owner.
+A contract sends tokens by creating a transfer, which is placed in its output comap and so is given to the next contract it calls.
+The called contract then claims the transfer to receive the tokens. Until claimed, the value of pending transfers is not counted in the
+balance of the sender, and a contract can not create transfers exceeding its balance.
+
+Getting balances requires external access, creating and claiming transfers requires external modification.
 */
 
 pub const GLOBAL_STORAGE_FEATURE: u32 = 2;
 
+/// Protected key storing the number of contracts deployed by an address
+pub const DEPLOY_NONCE_KEY: [u8; 2] = [0x01, 0x00];
+/// Protected key storing the native coin balance of an address
+pub const BALANCE_KEY: [u8; 2] = [0x01, 0x10];
+
+/// State writes by address, using raw (ie, including the '_' prefix for user keys) storage keys
+pub type StateWrites = HashMap<NeutronAddress, HashMap<Vec<u8>, Vec<u8>>>;
+
+/// Read access to state through a shared reference, so that executions running in parallel can read the same state
+pub trait StateSnapshot{
+    /// Reads a raw storage key, including the writes of all open checkpoints
+    fn read_raw_key(&self, address: &NeutronAddress, key: &[u8]) -> Result<Vec<u8>, NeutronError>;
+}
+
 #[derive(FromPrimitive)]
 pub enum GlobalStateFunctions{
     Available = 0, //reserved??
     StoreState = 1,
     LoadState,
     KeyExists,
+    CreateTokenTransfer,
+    ClaimTokenTransfer,
+    GetTokenBalance,
 }
 
 impl <'a>ElementAPI for (dyn GlobalState +'a){
@@ -69,6 +97,32 @@ pub trait GlobalState{
                 self.store_state(codata, &key, &value)?;
                 Ok(ElementResult::Result(0))
             }
+            GlobalStateFunctions::CreateTokenTransfer => {
+                //the transfer moves tokens kept in the storage of their owner once claimed
+                codata.permissions().assert_has_external_modification()?;
+                let owner = codata.pop_input_stack_address()?;
+                let id = codata.pop_input_stack_u64()?;
+                let value = codata.pop_input_stack_u64()?;
+                self.create_token_transfer(codata, owner, id, value)?;
+                Ok(ElementResult::Result(0))
+            },
+            GlobalStateFunctions::ClaimTokenTransfer => {
+                codata.permissions().assert_has_external_modification()?;
+                let owner = codata.pop_input_stack_address()?;
+                let id = codata.pop_input_stack_u64()?;
+                let value = self.claim_token_transfer(codata, owner, id)?;
+                codata.push_output_stack(&value.to_le_bytes())?;
+                Ok(ElementResult::Result(0))
+            },
+            GlobalStateFunctions::GetTokenBalance => {
+                codata.permissions().assert_has_external_access()?;
+                let owner = codata.pop_input_stack_address()?;
+                let id = codata.pop_input_stack_u64()?;
+                let address = codata.pop_input_stack_address()?;
+                let balance = self.get_token_balance(codata, owner, id, address)?;
+                codata.push_output_stack(&balance.to_le_bytes())?;
+                Ok(ElementResult::Result(0))
+            },
             GlobalStateFunctions::Available => {
                 Ok(ElementResult::Result(0))
             },
@@ -95,38 +149,119 @@ pub trait GlobalState{
         codata.push_output_transfer(owner, id, value);
         Ok(balance)
     }
+    /// Claims the transfer given to the current contract by its sender, returning the value claimed
     fn claim_token_transfer(&mut self, codata: &mut CoData, owner: NeutronAddress, id: u64) -> Result<u64, NeutronError>{
         let c = codata.peek_context(0)?.clone();
-        let self_key = build_token_key(c.self_address, id);
-        let sender_key = build_token_key(c.sender, id);
-        let self_balance = self.get_token_balance(codata, owner, id, c.self_address).unwrap_or(0);
         let value = codata.element_pop_transfer(owner, id).unwrap_or(0);
-        let sender_balance = self.get_token_balance(codata, owner, id, c.sender)?;
-        if sender_balance < value{
+        if c.self_address == c.sender{
+            //a transfer to itself leaves the balance unchanged
+            return Ok(value);
+        }
+        //the transfer is no longer pending, so it must now be covered by the balance of the sender
+        if self.get_token_balance(codata, owner, id, c.sender)? < value{
             return Err(Unrecoverable(UnrecoverableError::DeveloperError));
         }
-        self.private_store_state_external(codata, owner, &self_key, &(self_balance + value).to_le_bytes())?;
-        self.private_store_state_external(codata, owner, &sender_key, &(sender_balance - value).to_le_bytes())?;
-        Ok(0)
+        let self_balance = self.get_stored_token_balance(codata, owner, id, c.self_address)?;
+        let sender_balance = self.get_stored_token_balance(codata, owner, id, c.sender)?;
+        self.private_store_state_external(codata, owner, &build_token_key(c.self_address, id), &(self_balance + value).to_le_bytes())?;
+        self.private_store_state_external(codata, owner, &build_token_key(c.sender, id), &(sender_balance - value).to_le_bytes())?;
+        Ok(value)
     }
     
+    /// Gets the token balance of the given address, excluding the value of transfers it has created which are still pending
     fn get_token_balance(&mut self, codata: &mut CoData, owner: NeutronAddress, id: u64, address: NeutronAddress) -> Result<u64, NeutronError>{
+        let balance = self.get_stored_token_balance(codata, owner, id, address)?;
+        Ok(balance.saturating_sub(codata.compute_outgoing_transfer_value(owner, id, address).unwrap_or(0)))
+    }
+    /// Gets the token balance of the given address as stored, including the value of transfers it has created which are still pending
+    fn get_stored_token_balance(&mut self, codata: &mut CoData, owner: NeutronAddress, id: u64, address: NeutronAddress) -> Result<u64, NeutronError>{
         let key = build_token_key(address, id);
         match self.private_load_state_external(codata, owner, &key){
             Ok(v) => {
-                let mut balance = u64::from_le_bytes(v.clone().try_into().unwrap());
-                balance -= codata.compute_outgoing_transfer_value(owner, id, address).unwrap_or(0);
-                Ok(balance)
+                Ok(u64::from_le_bytes(v.try_into().unwrap_or([0; 8])))
             },
             Err(_) => {
                 Ok(0)
             }
         }
     }
-    
+
+    /// Gets the number of contracts deployed by the given address
+    /// The nonce is host bookkeeping, so it is accessed regardless of the permissions of the current context
+    fn get_deploy_nonce(&mut self, codata: &mut CoData, address: NeutronAddress) -> Result<u64, NeutronError>{
+        let v = codata.with_ignored_permissions(|codata| self.private_load_state_external(codata, address, &DEPLOY_NONCE_KEY));
+        match v{
+            Ok(v) => {
+                Ok(u64::from_le_bytes(v.try_into().unwrap_or([0; 8])))
+            },
+            Err(_) => {
+                Ok(0)
+            }
+        }
+    }
+    /// Increments the deployment nonce of the given address, returning the nonce before incrementing
+    fn increment_deploy_nonce(&mut self, codata: &mut CoData, address: NeutronAddress) -> Result<u64, NeutronError>{
+        let nonce = self.get_deploy_nonce(codata, address)?;
+        codata.with_ignored_permissions(|codata| {
+            self.private_store_state_external(codata, address, &DEPLOY_NONCE_KEY, &(nonce + 1).to_le_bytes())
+        })?;
+        Ok(nonce)
+    }
+
+    /// Gets the native coin balance of the given address
+    /// Balances are host bookkeeping, so they are accessed regardless of the permissions of the current context
+    fn get_balance(&mut self, codata: &mut CoData, address: NeutronAddress) -> Result<u64, NeutronError>{
+        let v = codata.with_ignored_permissions(|codata| self.private_load_state_external(codata, address, &BALANCE_KEY));
+        match v{
+            Ok(v) => {
+                Ok(u64::from_le_bytes(v.try_into().unwrap_or([0; 8])))
+            },
+            Err(_) => {
+                Ok(0)
+            }
+        }
+    }
+    /// Sets the native coin balance of the given address. This creates (or destroys) coins, so it is meant only for the host
+    fn set_balance(&mut self, codata: &mut CoData, address: NeutronAddress, value: u64) -> Result<(), NeutronError>{
+        codata.with_ignored_permissions(|codata| self.private_store_state_external(codata, address, &BALANCE_KEY, &value.to_le_bytes()))
+    }
+    /// Moves native coins between addresses, failing with LowTokenBalance if `from` does not hold enough coins
+    /// Permissions are not checked, this is left to the caller
+    fn transfer_coins(&mut self, codata: &mut CoData, from: NeutronAddress, to: NeutronAddress, value: u64) -> Result<(), NeutronError>{
+        let from_balance = self.get_balance(codata, from)?;
+        if from_balance < value{
+            return Err(Recoverable(RecoverableError::LowTokenBalance));
+        }
+        if value == 0 || from == to{
+            return Ok(());
+        }
+        let to_balance = self.get_balance(codata, to)?;
+        //the total supply of coins fits in a u64, so this can only overflow if balances were set up inconsistently
+        let to_balance = match to_balance.checked_add(value){
+            Some(v) => v,
+            None => return Err(Unrecoverable(UnrecoverableError::DeveloperError))
+        };
+        self.set_balance(codata, from, from_balance - value)?;
+        self.set_balance(codata, to, to_balance)
+    }
+
     fn create_checkpoint(&mut self, codata: &mut CoData) -> Result<(), NeutronError>;
     fn revert_checkpoint(&mut self, codata: &mut CoData) -> Result<(), NeutronError>;
     fn commit_checkpoint(&mut self, codata: &mut CoData) -> Result<(), NeutronError>;
+    /// Returns the writes made within the current checkpoint, including those of committed checkpoints above it
+    /// Implementations which do not track this may return no writes
+    fn checkpoint_writes(&mut self, _codata: &mut CoData) -> Result<StateWrites, NeutronError>{
+        Ok(StateWrites::new())
+    }
+    /// Applies writes made outside of this state (ie, by an execution against a snapshot of it) to the current checkpoint
+    /// This is meant only for the host, so permissions are not checked
+    fn apply_writes(&mut self, codata: &mut CoData, writes: StateWrites) -> Result<(), NeutronError>{
+        codata.with_ignored_permissions(|codata| {
+            writes.into_iter().try_for_each(|(address, kv)| {
+                kv.into_iter().try_for_each(|(key, value)| self.private_store_state_external(codata, address, &key, &value))
+            })
+        })
+    }
 }
 
 pub fn build_token_key(token_owner: NeutronAddress, id: u64) -> Vec<u8>{
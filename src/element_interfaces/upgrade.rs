@@ -0,0 +1,233 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::element_interfaces::storage::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use crate::vmmanager::*;
+use neutron_common::*;
+/*
+## Contract Upgrade
+
+ID: 6
+
+Functions:
+
+* [0] Available()
+* [1] UpgradeContract(code: comap "!.c", data: comap "!.d") -> ()
+* [2] IsUpgradeable() -> (upgradeable: u32)
+
+Contracts are flagged as upgradeable at deployment, by setting the CONTRACT_FLAG_UPGRADEABLE bit of the u32 flags given in the
+"!.f" comap key alongside the code. The flags are stored in protected storage and can not be changed afterwards.
+
+UpgradeContract replaces the code (and data, if "!.d" is given) of the calling contract with those in its output comap. This requires
+self modification permission and that the contract is flagged as upgradeable. The new code is validated and stored by the VM of the
+contract the same as at deployment, so VM specific keys may be given as well, such as the entry point "!.e" of NARM. VMs which can not
+validate new code do not support upgrades, which fails with ContractNotUpgradeable. The currently running code is not affected, the
+new code is used starting with the next execution of the contract.
+
+Because the code of an upgradeable contract can change, a pure call of an upgradeable contract fails with PureCallOfImpureContract.
+*/
+
+pub const CONTRACT_UPGRADE_FEATURE: u32 = 6;
+
+/// Protected storage key holding the deploy-time flags of a contract
+pub const CONTRACT_FLAGS_KEY: [u8; 2] = [0x02, 0x20];
+/// Contract flag: the code and data of the contract can be replaced using UpgradeContract
+pub const CONTRACT_FLAG_UPGRADEABLE: u32 = 1;
+
+#[derive(FromPrimitive)]
+pub enum ContractUpgradeFunctions {
+    Available = 0, //reserved??
+    UpgradeContract = 1,
+    IsUpgradeable = 2,
+}
+
+#[derive(Default)]
+pub struct ContractUpgrader {
+    /// The VMs contracts can use, so that new code is validated by the VM of the upgraded contract
+    pub vmm: VMManager,
+}
+
+impl ContractUpgrader {
+    pub fn new(vmm: VMManager) -> ContractUpgrader {
+        ContractUpgrader { vmm }
+    }
+}
+
+impl ElementAPI for ContractUpgrader {
+    fn system_call(
+        &mut self,
+        callsystem: &CallSystem,
+        codata: &mut CoData,
+        feature: u32,
+        function: u32,
+    ) -> Result<ElementResult, NeutronError> {
+        if feature != CONTRACT_UPGRADE_FEATURE {
+            return Ok(ElementResult::Result(0));
+        }
+        let f = num::FromPrimitive::from_u32(function);
+        if f.is_none() {
+            return Err(Recoverable(RecoverableError::InvalidSystemFunction));
+        }
+        let f = f.unwrap();
+        let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+        match f {
+            ContractUpgradeFunctions::Available => Ok(ElementResult::Result(1)),
+            ContractUpgradeFunctions::UpgradeContract => {
+                codata.permissions().assert_has_self_modification()?;
+                if load_contract_flags(&mut **storage, codata)? & CONTRACT_FLAG_UPGRADEABLE == 0 {
+                    return Err(Host(HostError::ContractNotUpgradeable));
+                }
+                let mut hypervisor = match self.vmm.vm_builders.get(&codata.current_context().self_address.version) {
+                    Some(builder) => builder(),
+                    None => return Err(Recoverable(RecoverableError::InvalidVM)),
+                };
+                hypervisor.upgrade_code(codata, &mut **storage)?;
+                Ok(ElementResult::Result(0))
+            }
+            ContractUpgradeFunctions::IsUpgradeable => {
+                let flags = load_contract_flags(&mut **storage, codata)?;
+                Ok(ElementResult::Result((flags & CONTRACT_FLAG_UPGRADEABLE != 0) as u64))
+            }
+        }
+    }
+}
+
+/// Decodes contract flags as given in the "!.f" comap key at deployment. Missing flags are treated as 0
+pub fn decode_contract_flags(flags: Option<Vec<u8>>) -> u32 {
+    let mut bytes = [0u8; 4];
+    if let Some(v) = flags {
+        let len = v.len().min(4);
+        bytes[0..len].copy_from_slice(&v[0..len]);
+    }
+    u32::from_le_bytes(bytes)
+}
+
+/// Loads the flags of the current contract
+/// Flags (like bytecode) are loaded regardless of the permissions of the current context
+pub fn load_contract_flags(storage: &mut dyn GlobalState, codata: &mut CoData) -> Result<u32, NeutronError> {
+    let v = codata.with_ignored_permissions(|codata| storage.private_load_state(codata, &CONTRACT_FLAGS_KEY));
+    //contracts deployed before flags existed have no flags
+    Ok(decode_contract_flags(v.ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryGlobalState;
+    use crate::interface::*;
+    use crate::narm_hypervisor::*;
+    use std::cell::RefCell;
+
+    /// A VM which does not support upgrades
+    struct OtherVM {}
+    impl VMHypervisor for OtherVM {
+        fn enter_state(&mut self, _codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            Ok(())
+        }
+        fn execute(&mut self, _codata: &mut CoData) -> Result<VMResult, NeutronError> {
+            Ok(VMResult::Ended(0))
+        }
+        fn set_result(&mut self, _result: u64) {}
+        fn set_error(&mut self, _code: u64) {}
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            Ok(())
+        }
+    }
+
+    fn upgrade(flags: u32, permissions: ContextPermissions, code: &[u8]) -> (Result<ElementResult, NeutronError>, MemoryGlobalState) {
+        upgrade_version(2, flags, permissions, code)
+    }
+
+    fn upgrade_version(
+        version: u32,
+        flags: u32,
+        permissions: ContextPermissions,
+        code: &[u8],
+    ) -> (Result<ElementResult, NeutronError>, MemoryGlobalState) {
+        let mut codata = CoData::new();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut context = ExecutionContext::default();
+        context.permissions = permissions;
+        context.self_address.version = version;
+        db.write_key(&context.self_address, &[0x02, 0], &[1, 1]).unwrap();
+        db.write_key(&context.self_address, &CONTRACT_FLAGS_KEY, &flags.to_le_bytes())
+            .unwrap();
+        codata.push_context(context).unwrap();
+        codata.push_output_key("!.c".as_bytes(), code).unwrap();
+
+        let mut vmm = VMManager::default();
+        let narm = || -> Box<dyn VMHypervisor> { Box::from(NarmHypervisor::default()) };
+        let other = || -> Box<dyn VMHypervisor> { Box::from(OtherVM {}) };
+        vmm.vm_builders.insert(2, narm);
+        vmm.vm_builders.insert(3, other);
+        let mut upgrader = ContractUpgrader::new(vmm);
+        let result = {
+            let mut callsystem = CallSystem::default();
+            callsystem.global_storage = Some(RefCell::new(&mut db));
+            callsystem.add_call(CONTRACT_UPGRADE_FEATURE, &mut upgrader).unwrap();
+            codata.enter_element();
+            let result = callsystem.call(
+                &mut codata,
+                CONTRACT_UPGRADE_FEATURE,
+                ContractUpgradeFunctions::UpgradeContract as u32,
+            );
+            codata.exit_element();
+            result
+        };
+        (result, db)
+    }
+
+    #[test]
+    fn test_upgrade_contract() {
+        let (result, mut db) = upgrade(CONTRACT_FLAG_UPGRADEABLE, ContextPermissions::mutable_call(), &[2, 2]);
+        assert!(result.is_ok());
+        let mut address = NeutronAddress::default();
+        address.version = 2;
+        assert_eq!(db.read_key(&address, &[0x02, 0]).unwrap(), vec![2, 2]);
+        //without "!.e" the new code is entered at its start
+        assert_eq!(
+            db.read_key(&address, &CONTRACT_ENTRY_KEY).unwrap(),
+            decode_contract_entry(None).to_le_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn test_upgrade_not_upgradeable() {
+        let (result, mut db) = upgrade(0, ContextPermissions::mutable_call(), &[2, 2]);
+        assert_eq!(result.err(), Some(Host(HostError::ContractNotUpgradeable)));
+        let mut address = NeutronAddress::default();
+        address.version = 2;
+        assert_eq!(db.read_key(&address, &[0x02, 0]).unwrap(), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_upgrade_requires_self_modification() {
+        let (result, _) = upgrade(CONTRACT_FLAG_UPGRADEABLE, ContextPermissions::immutable_call(), &[2, 2]);
+        assert_eq!(result.err(), Some(Recoverable(RecoverableError::RequiresPermissionSelfMod)));
+    }
+
+    #[test]
+    fn test_upgrade_validates_code() {
+        //cpsid i
+        let (result, mut db) = upgrade(CONTRACT_FLAG_UPGRADEABLE, ContextPermissions::mutable_call(), &[0x72, 0xB6]);
+        assert_eq!(result.err(), Some(Host(HostError::UnsupportedInstruction)));
+        let mut address = NeutronAddress::default();
+        address.version = 2;
+        assert_eq!(db.read_key(&address, &[0x02, 0]).unwrap(), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_upgrade_uses_vm_of_contract() {
+        //the code is not NARM code, but is left to the VM of the contract, which does not support upgrades
+        let (result, mut db) = upgrade_version(3, CONTRACT_FLAG_UPGRADEABLE, ContextPermissions::mutable_call(), &[0x72, 0xB6]);
+        assert_eq!(result.err(), Some(Host(HostError::ContractNotUpgradeable)));
+        let mut address = NeutronAddress::default();
+        address.version = 3;
+        assert_eq!(db.read_key(&address, &[0x02, 0]).unwrap(), vec![1, 1]);
+
+        let (result, _) = upgrade_version(4, CONTRACT_FLAG_UPGRADEABLE, ContextPermissions::mutable_call(), &[2, 2]);
+        assert_eq!(result.err(), Some(Recoverable(RecoverableError::InvalidVM)));
+    }
+}
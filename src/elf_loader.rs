@@ -0,0 +1,258 @@
+//! Loading of NARM smart contracts from ELF executables
+
+use crate::codata::*;
+use crate::narm_hypervisor::*;
+use crate::narm_validator::{MAX_CODE_SIZE, MAX_DATA_SIZE};
+use crate::neutronerror::*;
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_TYPE_EXECUTABLE: u16 = 2;
+const ELF_MACHINE_ARM: u16 = 40;
+const ELF_HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const PT_LOAD: u32 = 1;
+
+/// The code, data and entry point of a NARM smart contract, as given to the NarmHypervisor
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NarmImage {
+    /// The read-only memory of the contract (ie, .text and .rodata), mapped at CODE_ADDRESS
+    pub code: Vec<u8>,
+    /// The initial writeable memory of the contract (ie, .data followed by a zeroed .bss), mapped at DATA_ADDRESS
+    pub data: Vec<u8>,
+    /// The address execution begins at. The lowest bit is set for Thumb code, as in the ELF entry point
+    pub entry: u32,
+}
+
+impl NarmImage {
+    /// Pushes the image as the "!.c", "!.d" and "!.e" keys of the input comap, as used for bare executions and deployments
+    pub fn push_input_keys(&self, codata: &mut CoData) -> Result<(), NeutronError> {
+        codata.push_input_key("!.c".as_bytes(), &self.code)?;
+        codata.push_input_key("!.d".as_bytes(), &self.data)?;
+        codata.push_input_key("!.e".as_bytes(), &self.entry.to_le_bytes())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ElfLoadError {
+    /// The file could not be read
+    Io(String),
+    /// The file does not begin with the ELF magic number
+    NotElf,
+    /// The file is not a 32 bit little endian ARM executable
+    UnsupportedFormat,
+    /// A header or segment extends past the end of the file
+    Truncated,
+    /// A loadable segment is not within the code or data regions of the NARM memory map
+    InvalidSegmentAddress(u32),
+    /// A loadable segment extends the code or data beyond the maximum size of a contract
+    SegmentTooLarge(u32),
+    /// The entry point is not within the loaded code
+    InvalidEntryPoint(u32),
+}
+
+impl fmt::Display for ElfLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfLoadError::Io(e) => write!(f, "unable to read ELF file: {}", e),
+            ElfLoadError::NotElf => write!(f, "not an ELF file"),
+            ElfLoadError::UnsupportedFormat => write!(f, "not a 32 bit little endian ARM executable"),
+            ElfLoadError::Truncated => write!(f, "ELF file is truncated"),
+            ElfLoadError::InvalidSegmentAddress(a) => {
+                write!(f, "segment at {:#010x} is outside of the NARM code and data regions", a)
+            }
+            ElfLoadError::SegmentTooLarge(a) => write!(f, "segment at {:#010x} exceeds the maximum contract size", a),
+            ElfLoadError::InvalidEntryPoint(a) => write!(f, "entry point {:#010x} is outside of the code", a),
+        }
+    }
+}
+
+impl std::error::Error for ElfLoadError {}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, ElfLoadError> {
+    let b = bytes.get(offset..offset + 2).ok_or(ElfLoadError::Truncated)?;
+    Ok(u16::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ElfLoadError> {
+    let b = bytes.get(offset..offset + 4).ok_or(ElfLoadError::Truncated)?;
+    Ok(u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+/// Copies a segment into a memory image starting at `base`, extending the image with zeros as needed up to `max_size`
+fn place_segment(
+    image: &mut Vec<u8>,
+    base: u32,
+    max_size: usize,
+    address: u32,
+    contents: &[u8],
+    memory_size: u32,
+) -> Result<(), ElfLoadError> {
+    let start = (address - base) as usize;
+    let end = start + memory_size as usize;
+    //checked before resizing, as the regions are far larger than any contract may be
+    if end > max_size {
+        return Err(ElfLoadError::SegmentTooLarge(address));
+    }
+    if image.len() < end {
+        image.resize(end, 0);
+    }
+    image[start..start + contents.len()].copy_from_slice(contents);
+    Ok(())
+}
+
+/// Loads the PT_LOAD segments of an ELF executable into a NarmImage
+/// Segments within the code region form the code of the contract and segments within the data region form its data. Memory of a
+/// segment beyond its contents in the file (ie, .bss) is zeroed.
+pub fn load_elf(bytes: &[u8]) -> Result<NarmImage, ElfLoadError> {
+    if bytes.get(0..4) != Some(&ELF_MAGIC[..]) {
+        return Err(ElfLoadError::NotElf);
+    }
+    if bytes.len() < ELF_HEADER_SIZE {
+        return Err(ElfLoadError::Truncated);
+    }
+    if bytes[4] != ELF_CLASS_32
+        || bytes[5] != ELF_DATA_LITTLE_ENDIAN
+        || read_u16(bytes, 16)? != ELF_TYPE_EXECUTABLE
+        || read_u16(bytes, 18)? != ELF_MACHINE_ARM
+    {
+        return Err(ElfLoadError::UnsupportedFormat);
+    }
+    let entry = read_u32(bytes, 24)?;
+    let program_headers = read_u32(bytes, 28)? as usize;
+    let program_header_size = read_u16(bytes, 42)? as usize;
+    let program_header_count = read_u16(bytes, 44)? as usize;
+    if program_header_count > 0 && program_header_size < PROGRAM_HEADER_SIZE {
+        return Err(ElfLoadError::UnsupportedFormat);
+    }
+
+    let mut image = NarmImage::default();
+    for i in 0..program_header_count {
+        let header = program_headers
+            .checked_add(i * program_header_size)
+            .ok_or(ElfLoadError::Truncated)?;
+        if read_u32(bytes, header)? != PT_LOAD {
+            continue;
+        }
+        let offset = read_u32(bytes, header + 4)? as usize;
+        let address = read_u32(bytes, header + 8)?;
+        let file_size = read_u32(bytes, header + 16)?;
+        let memory_size = read_u32(bytes, header + 20)?;
+        if memory_size == 0 {
+            continue;
+        }
+        if file_size > memory_size {
+            return Err(ElfLoadError::UnsupportedFormat);
+        }
+        let contents = offset
+            .checked_add(file_size as usize)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or(ElfLoadError::Truncated)?;
+        let end = address as u64 + memory_size as u64;
+        if address >= CODE_ADDRESS && end <= CODE_REGION_END as u64 {
+            place_segment(&mut image.code, CODE_ADDRESS, MAX_CODE_SIZE, address, contents, memory_size)?;
+        } else if address >= DATA_ADDRESS && end <= DATA_REGION_END as u64 {
+            place_segment(&mut image.data, DATA_ADDRESS, MAX_DATA_SIZE, address, contents, memory_size)?;
+        } else {
+            return Err(ElfLoadError::InvalidSegmentAddress(address));
+        }
+    }
+    //the lowest bit only marks the entry point as Thumb code
+    let entry_address = entry & !1;
+    if entry_address < CODE_ADDRESS || (entry_address - CODE_ADDRESS) as usize >= image.code.len() {
+        return Err(ElfLoadError::InvalidEntryPoint(entry));
+    }
+    image.entry = entry;
+    Ok(image)
+}
+
+/// Reads and loads an ELF executable
+pub fn load_elf_file<P: AsRef<Path>>(path: P) -> Result<NarmImage, ElfLoadError> {
+    let bytes = std::fs::read(path).map_err(|e| ElfLoadError::Io(e.to_string()))?;
+    load_elf(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A segment as (address, contents, memory size)
+    type Segment<'a> = (u32, &'a [u8], u32);
+
+    /// Builds an ARM executable with a PT_LOAD program header for each segment
+    fn build_elf(entry: u32, segments: &[Segment]) -> Vec<u8> {
+        let mut elf = ELF_MAGIC.to_vec();
+        elf.extend_from_slice(&[ELF_CLASS_32, ELF_DATA_LITTLE_ENDIAN, 1]);
+        elf.resize(16, 0);
+        elf.extend_from_slice(&ELF_TYPE_EXECUTABLE.to_le_bytes());
+        elf.extend_from_slice(&ELF_MACHINE_ARM.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes()); //version
+        elf.extend_from_slice(&entry.to_le_bytes());
+        elf.extend_from_slice(&(ELF_HEADER_SIZE as u32).to_le_bytes()); //program headers
+        elf.extend_from_slice(&0u32.to_le_bytes()); //section headers
+        elf.extend_from_slice(&0u32.to_le_bytes()); //flags
+        elf.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+        elf.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        elf.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+        elf.extend_from_slice(&[0; 6]); //section header size, count and string table index
+        let mut offset = ELF_HEADER_SIZE + segments.len() * PROGRAM_HEADER_SIZE;
+        for (address, contents, memory_size) in segments {
+            let size = contents.len() as u32;
+            //type, offset, virtual and physical address, file and memory size, flags and alignment
+            for field in &[PT_LOAD, offset as u32, *address, *address, size, *memory_size, 0, 4] {
+                elf.extend_from_slice(&field.to_le_bytes());
+            }
+            offset += contents.len();
+        }
+        for (_, contents, _) in segments {
+            elf.extend_from_slice(contents);
+        }
+        elf
+    }
+
+    #[test]
+    fn test_load_segments() {
+        let elf = build_elf(
+            CODE_ADDRESS + 1,
+            &[
+                (CODE_ADDRESS, &[1, 2, 3, 4], 4),
+                (CODE_ADDRESS + 8, &[5, 6], 2),
+                (DATA_ADDRESS, &[7, 8], 6),
+            ],
+        );
+        let image = load_elf(&elf).unwrap();
+        //.rodata is placed after a gap in the code, and .bss is zeroed after .data
+        assert_eq!(image.code, vec![1, 2, 3, 4, 0, 0, 0, 0, 5, 6]);
+        assert_eq!(image.data, vec![7, 8, 0, 0, 0, 0]);
+        assert_eq!(image.entry, CODE_ADDRESS + 1);
+    }
+
+    #[test]
+    fn test_invalid_elf() {
+        assert_eq!(load_elf(&[1, 2, 3]), Err(ElfLoadError::NotElf));
+        let elf = build_elf(CODE_ADDRESS, &[(CODE_ADDRESS, &[1, 2], 2)]);
+        assert_eq!(load_elf(&elf[0..40]), Err(ElfLoadError::Truncated));
+        assert_eq!(load_elf(&elf[0..elf.len() - 1]), Err(ElfLoadError::Truncated));
+
+        let mut big_endian = elf.clone();
+        big_endian[5] = 2;
+        assert_eq!(load_elf(&big_endian), Err(ElfLoadError::UnsupportedFormat));
+
+        let elf = build_elf(CODE_ADDRESS, &[(STACK_ADDRESS, &[1, 2], 2)]);
+        assert_eq!(load_elf(&elf), Err(ElfLoadError::InvalidSegmentAddress(STACK_ADDRESS)));
+        let elf = build_elf(CODE_ADDRESS, &[(0x7FFF_0000, &[1, 2], 2)]);
+        assert_eq!(load_elf(&elf), Err(ElfLoadError::SegmentTooLarge(0x7FFF_0000)));
+        let elf = build_elf(CODE_ADDRESS, &[(DATA_ADDRESS, &[], MAX_DATA_SIZE as u32 + 1)]);
+        assert_eq!(load_elf(&elf), Err(ElfLoadError::SegmentTooLarge(DATA_ADDRESS)));
+        let elf = build_elf(CODE_ADDRESS + 2, &[(CODE_ADDRESS, &[1, 2], 2)]);
+        assert_eq!(load_elf(&elf), Err(ElfLoadError::InvalidEntryPoint(CODE_ADDRESS + 2)));
+        assert_eq!(
+            ElfLoadError::InvalidEntryPoint(CODE_ADDRESS).to_string(),
+            "entry point 0x00010000 is outside of the code"
+        );
+    }
+}
@@ -1,31 +1,76 @@
 //! The harness module contains a series of methods and structures for more easily using Neutron for various testing purposes
 
-extern crate elf;
-
+use crate::block_executor::*;
 use crate::callsystem::*;
 use crate::codata::*;
+use crate::contract_address::*;
 use crate::db::MemoryGlobalState;
+use crate::element_interfaces::call_contract::*;
+use crate::element_interfaces::chain_info::*;
+use crate::element_interfaces::coins::*;
 use crate::element_interfaces::debug_data::*;
+use crate::element_interfaces::events::*;
+use crate::element_interfaces::hashing::*;
 use crate::element_interfaces::logging::StdoutLogger;
+use crate::element_interfaces::signatures::*;
+use crate::element_interfaces::storage::GlobalState;
+use crate::element_interfaces::upgrade::*;
+use crate::elf_loader::*;
 use crate::interface::*;
 use crate::manager::*;
 use crate::narm_hypervisor::*;
+use crate::scheduler::*;
 use crate::vmmanager::*;
+use neutron_common::NeutronAddress;
 
 use std::cell::RefCell;
-use std::path::PathBuf;
 
 pub const DEFAULT_TEST_GAS: u64 = 10000;
+/// The VM number NARM is registered as in the harness
+pub const NARM_VM_VERSION: u32 = 2;
 
-/// TestHarness contains a NeutronInstance and test versions of "mandatory" Elements, plus the optional DebugDataInjector Element
-#[derive(Default)]
+/// TestHarness contains a NeutronInstance and test versions of "mandatory" Elements, plus the optional ContractCaller, ContractUpgrader, ChainInfo, Coins, EventEmitter, Hasher, SignatureVerifier and DebugDataInjector Elements
 pub struct TestHarness {
     pub instance: NeutronInstance,
     pub db: MemoryGlobalState,
     pub logger: StdoutLogger,
+    pub caller: ContractCaller,
+    pub upgrader: ContractUpgrader,
+    /// The block information given to contracts, which tests are free to change
+    pub chaininfo: ChainInfo,
+    pub coins: Coins,
+    pub events: EventEmitter,
+    pub hasher: Hasher,
+    pub verifier: SignatureVerifier,
     pub debugdata: DebugDataInjector,
 }
 
+impl Default for TestHarness {
+    fn default() -> TestHarness {
+        TestHarness {
+            instance: NeutronInstance::default(),
+            db: MemoryGlobalState::default(),
+            logger: StdoutLogger::default(),
+            caller: ContractCaller::default(),
+            upgrader: ContractUpgrader::new(default_vmm()),
+            chaininfo: ChainInfo::default(),
+            coins: Coins::default(),
+            events: EventEmitter::default(),
+            hasher: Hasher::default(),
+            verifier: SignatureVerifier::default(),
+            debugdata: DebugDataInjector::default(),
+        }
+    }
+}
+
+/// Builds the VMManager used by the harness, with NARM registered as NARM_VM_VERSION
+pub fn default_vmm() -> VMManager {
+    let mut vmm = VMManager::default();
+    let narm = || -> Box<dyn VMHypervisor> { Box::from(NarmHypervisor::default()) };
+    vmm.vm_builders.insert(NARM_VM_VERSION, narm);
+    vmm
+}
+
 /// Contains the execution state data needed to run Neutron which is also likely to be interacted with through test code
 #[derive(Default)]
 pub struct NeutronInstance {
@@ -37,9 +82,7 @@ impl NeutronInstance {
     /// Loads the given binary at `path_str` and loads it for a "use once" execution using the given CallSystem and Context
     pub fn execute_binary(&mut self, path_str: &str, callsystem: &CallSystem, mut context: ExecutionContext) -> NeutronResult {
         self.prepare_execute(path_str, &mut context);
-        let mut vmm = VMManager::default();
-        let narm = || -> Box<dyn VMHypervisor> { Box::from(NarmHypervisor::default()) };
-        vmm.vm_builders.insert(2, narm);
+        let vmm = default_vmm();
 
         let result = self.manager.execute(&mut self.codata, &callsystem, &vmm).unwrap();
         NeutronInstance::print_results(&result);
@@ -47,11 +90,7 @@ impl NeutronInstance {
     }
 
     fn prepare_execute(&mut self, path_str: &str, context: &mut ExecutionContext) {
-        let path = PathBuf::from(path_str);
-        let binary = elf::File::open_path(path).unwrap();
-
-        let text_scn = binary.get_section(".text").unwrap();
-        assert!(text_scn.shdr.addr == 0x10000);
+        let image = load_elf_file(path_str).unwrap_or_else(|e| panic!("Unable to load {}: {}", path_str, e));
 
         if context.gas_limit == 0 {
             context.gas_limit = DEFAULT_TEST_GAS;
@@ -63,16 +102,11 @@ impl NeutronInstance {
         //self.codata.gas_remaining = MAX_GAS;
 
         self.codata.push_context(context.clone()).unwrap();
-        self.codata.push_input_key("!.c".as_bytes(), &text_scn.data).unwrap();
-        self.codata.push_input_key("!.d".as_bytes(), &[0]).unwrap();
+        image.push_input_keys(&mut self.codata).unwrap();
     }
 
     fn prepare_deploy(&mut self, path_str: &str, context: &mut ExecutionContext) {
-        let path = PathBuf::from(path_str);
-        let binary = elf::File::open_path(path).unwrap();
-
-        let text_scn = binary.get_section(".text").unwrap();
-        assert!(text_scn.shdr.addr == 0x10000);
+        let image = load_elf_file(path_str).unwrap_or_else(|e| panic!("Unable to load {}: {}", path_str, e));
 
         if context.gas_limit == 0 {
             context.gas_limit = DEFAULT_TEST_GAS;
@@ -84,11 +118,12 @@ impl NeutronInstance {
         //self.codata.gas_remaining = MAX_GAS;
 
         self.codata.push_context(context.clone()).unwrap();
-        self.codata.push_input_key("!.c".as_bytes(), &text_scn.data).unwrap();
-        self.codata.push_input_key("!.d".as_bytes(), &[0]).unwrap();
+        image.push_input_keys(&mut self.codata).unwrap();
     }
 
     fn print_results(result: &NeutronResult) {
+        //test executions are expected to succeed, so a revert is treated the same as an error
+        assert!(!result.reverted, "Contract execution reverted with error code {:x}", result.status);
         println!("Contract executed successfully!");
         println!("Gas used: {}", result.gas_used);
         println!("Status code: {:x}", result.status);
@@ -96,41 +131,59 @@ impl NeutronInstance {
 }
 
 impl TestHarness {
+    /// Executes the top context of the instance using the default test CallSystem, built from the Elements of the harness
+    fn execute_instance(&mut self) -> NeutronResult {
+        let elements = DefaultElements {
+            caller: &mut self.caller,
+            upgrader: &mut self.upgrader,
+            chaininfo: &mut self.chaininfo,
+            coins: &mut self.coins,
+            events: &mut self.events,
+            hasher: &mut self.hasher,
+            verifier: &mut self.verifier,
+            debugdata: Some(&mut self.debugdata),
+        };
+        let cs = elements.callsystem(&mut self.db, &mut self.logger);
+        let vmm = default_vmm();
+        let result = self.instance.manager.execute(&mut self.instance.codata, &cs, &vmm).unwrap();
+        NeutronInstance::print_results(&result);
+        result
+    }
+
     /// Uses the default test CallSystem to "use once" execute the given smart contract binary
     pub fn execute_binary_using_default_callsystem(&mut self, path_str: &str, mut context: ExecutionContext) -> NeutronResult {
         self.instance.prepare_execute(path_str, &mut context);
-        let mut vmm = VMManager::default();
-        let narm = || -> Box<dyn VMHypervisor> { Box::from(NarmHypervisor::default()) };
-        vmm.vm_builders.insert(2, narm);
-
         self.db.checkpoint().unwrap();
-        let mut cs = CallSystem::default();
-        cs.global_storage = Some(RefCell::new(&mut self.db));
-        cs.logging = Some(RefCell::new(&mut self.logger));
-        cs.add_call(DEBUG_DATA_FEATURE, &mut self.debugdata).unwrap();
-
-        let result = self.instance.manager.execute(&mut self.instance.codata, &cs, &vmm).unwrap();
-        NeutronInstance::print_results(&result);
+        let result = self.execute_instance();
 
         self.db.commit().unwrap();
         result
     }
 
+    /// Sets the native coin balance of the given address, creating coins for use in tests
+    pub fn set_balance(&mut self, address: NeutronAddress, value: u64) {
+        self.db.checkpoint().unwrap();
+        self.db.set_balance(&mut self.instance.codata, address, value).unwrap();
+        self.db.commit().unwrap();
+    }
+
+    /// Returns the native coin balance of the given address
+    pub fn balance(&mut self, address: NeutronAddress) -> u64 {
+        self.db.get_balance(&mut self.instance.codata, address).unwrap()
+    }
+
+    /// Returns the address the next deployment by `sender` will be given
+    pub fn next_contract_address(&mut self, sender: NeutronAddress) -> NeutronAddress {
+        peek_next_contract_address(&mut self.db, &mut self.instance.codata, sender, NARM_VM_VERSION).unwrap()
+    }
+
     /// Loads the given smart contract binary and deploys it for multiple uses with the default test CallSystem
+    /// The contract is deployed to an address derived from the sender of the context, which can be known beforehand using `next_contract_address`
     pub fn deploy_binary_using_default_callsystem(&mut self, path_str: &str, mut context: ExecutionContext) -> NeutronResult {
-        self.instance.prepare_deploy(path_str, &mut context);
-        let mut vmm = VMManager::default();
-        let narm = || -> Box<dyn VMHypervisor> { Box::from(NarmHypervisor::default()) };
-        vmm.vm_builders.insert(2, narm);
-
         self.db.checkpoint().unwrap();
-        let mut cs = CallSystem::default();
-        cs.global_storage = Some(RefCell::new(&mut self.db));
-        cs.logging = Some(RefCell::new(&mut self.logger));
-        cs.add_call(DEBUG_DATA_FEATURE, &mut self.debugdata).unwrap();
-
-        let result = self.instance.manager.execute(&mut self.instance.codata, &cs, &vmm).unwrap();
-        NeutronInstance::print_results(&result);
+        context.self_address = next_contract_address(&mut self.db, &mut self.instance.codata, context.sender, NARM_VM_VERSION).unwrap();
+        self.instance.prepare_deploy(path_str, &mut context);
+        let result = self.execute_instance();
 
         self.db.commit().unwrap();
         result
@@ -146,20 +199,115 @@ impl TestHarness {
         context.permissions = ContextPermissions::mutable_call();
         context.execution_type = ExecutionType::Call;
         self.instance.codata.push_context(context.clone()).unwrap();
-        let mut vmm = VMManager::default();
-        let narm = || -> Box<dyn VMHypervisor> { Box::from(NarmHypervisor::default()) };
-        vmm.vm_builders.insert(2, narm);
-
         self.db.checkpoint().unwrap();
-        let mut cs = CallSystem::default();
-        cs.global_storage = Some(RefCell::new(&mut self.db));
-        cs.logging = Some(RefCell::new(&mut self.logger));
-        cs.add_call(DEBUG_DATA_FEATURE, &mut self.debugdata).unwrap();
+        let result = self.execute_instance();
 
-        let result = self.instance.manager.execute(&mut self.instance.codata, &cs, &vmm).unwrap();
-        NeutronInstance::print_results(&result);
+        self.db.commit().unwrap();
+        result
+    }
+
+    /// Executes a batch of calls to previously deployed smart contracts using the default test CallSystem, in parallel where their permissions allow
+    /// Unlike the other harness functions, the permissions of each context are used as given. The DebugDataInjector is not available to batches
+    pub fn execute_batch_using_default_callsystem(&mut self, executions: &[ScheduledExecution]) -> Vec<NeutronResult> {
+        self.db.checkpoint().unwrap();
+        let block = self.chaininfo.block.clone();
+        let outcomes = Scheduler::default()
+            .execute_batch(&mut self.db, executions, |db, execution| {
+                execute_using_default_callsystem(db, execution, &block, &TransactionContext::default())
+            })
+            .unwrap();
+        self.db.commit().unwrap();
+        outcomes
+            .into_iter()
+            .map(|outcome| {
+                let result = outcome.unwrap();
+                NeutronInstance::print_results(&result);
+                result
+            })
+            .collect()
+    }
 
+    /// Executes the transactions of a block using the default test CallSystem, giving contracts the information of `block`
+    /// The ChainInfo of the harness is set to `block`, so that it stays in effect for later executions
+    pub fn execute_block_using_default_callsystem(&mut self, block: BlockContext, transactions: &[BlockTransaction]) -> BlockResult {
+        self.chaininfo.block = block;
+        self.db.checkpoint().unwrap();
+        let result = execute_block(&mut self.db, &self.chaininfo.block, transactions, |db, block, transaction| {
+            execute_using_default_callsystem(db, &transaction.execution, block, &transaction.transaction)
+        })
+        .unwrap();
         self.db.commit().unwrap();
         result
     }
 }
+
+/// Runs a single execution of a batch or block, with Elements of its own as batches are executed on multiple threads
+/// Executions of a batch are not part of a transaction, so they are given an empty TransactionContext
+fn execute_using_default_callsystem(
+    db: &mut dyn GlobalState,
+    execution: &ScheduledExecution,
+    block: &BlockContext,
+    transaction: &TransactionContext,
+) -> ExecutionOutcome {
+    let mut execution = execution.clone();
+    if execution.context.gas_limit == 0 {
+        execution.context.gas_limit = DEFAULT_TEST_GAS;
+    }
+    let mut codata = CoData::new();
+    execution.prepare(&mut codata)?;
+
+    let mut logger = StdoutLogger::default();
+    let mut caller = ContractCaller::default();
+    let mut upgrader = ContractUpgrader::new(default_vmm());
+    let mut chaininfo = ChainInfo::new(block.clone());
+    chaininfo.transaction = transaction.clone();
+    let mut coins = Coins::default();
+    let mut events = EventEmitter::default();
+    let mut hasher = Hasher::default();
+    let mut verifier = SignatureVerifier::default();
+    let elements = DefaultElements {
+        caller: &mut caller,
+        upgrader: &mut upgrader,
+        chaininfo: &mut chaininfo,
+        coins: &mut coins,
+        events: &mut events,
+        hasher: &mut hasher,
+        verifier: &mut verifier,
+        debugdata: None,
+    };
+    let cs = elements.callsystem(db, &mut logger);
+    Manager::default().execute(&mut codata, &cs, &default_vmm())
+}
+
+/// The optional Elements of the default test CallSystem
+struct DefaultElements<'a> {
+    caller: &'a mut ContractCaller,
+    upgrader: &'a mut ContractUpgrader,
+    chaininfo: &'a mut ChainInfo,
+    coins: &'a mut Coins,
+    events: &'a mut EventEmitter,
+    hasher: &'a mut Hasher,
+    verifier: &'a mut SignatureVerifier,
+    /// Not available to batches, as debug data is given by the harness for a single execution
+    debugdata: Option<&'a mut DebugDataInjector>,
+}
+
+impl<'a> DefaultElements<'a> {
+    /// Builds the default test CallSystem, registering each Element under its feature number
+    fn callsystem(self, db: &'a mut dyn GlobalState, logger: &'a mut StdoutLogger) -> CallSystem<'a> {
+        let mut cs = CallSystem::default();
+        cs.global_storage = Some(RefCell::new(db));
+        cs.logging = Some(RefCell::new(logger));
+        cs.add_call(CALL_CONTRACT_FEATURE, self.caller).unwrap();
+        cs.add_call(CONTRACT_UPGRADE_FEATURE, self.upgrader).unwrap();
+        cs.add_call(CHAIN_INFO_FEATURE, self.chaininfo).unwrap();
+        cs.add_call(COINS_FEATURE, self.coins).unwrap();
+        cs.add_call(EVENTS_FEATURE, self.events).unwrap();
+        cs.add_call(HASHING_FEATURE, self.hasher).unwrap();
+        cs.add_call(SIGNATURES_FEATURE, self.verifier).unwrap();
+        if let Some(debugdata) = self.debugdata {
+            cs.add_call(DEBUG_DATA_FEATURE, debugdata).unwrap();
+        }
+        cs
+    }
+}
@@ -86,6 +86,14 @@ impl ContextPermissions{
             access_external: true
         }
     }
+    /// Encodes the permissions as bit flags, as given to smart contracts
+    /// Bit 0 is modify_self, bit 1 is modify_external, bit 2 is access_external and bit 3 is access_self
+    pub fn to_flags(&self) -> u32{
+        (self.modify_self as u32)
+            | (self.modify_external as u32) << 1
+            | (self.access_external as u32) << 2
+            | (self.access_self as u32) << 3
+    }
     pub fn assert_has_self_access(&self) -> Result<(), NeutronError>{
         if !self.access_self{
             Err(NeutronError::Recoverable(RecoverableError::RequiresPermissionSelfAccess))
@@ -114,6 +122,31 @@ impl ContextPermissions{
             Ok(())
         }
     }
+    /// Checks that a context with these permissions may create a nested context with the `child` permissions, following the implications above
+    /// Permissions of a child at another address are "self" permissions from its own point of view, but are external permissions from the point of view of this context
+    pub fn assert_can_grant(&self, child: &ContextPermissions, same_address: bool) -> Result<(), NeutronError>{
+        if child.modify_external{
+            self.assert_has_external_modification()?;
+        }
+        if child.access_external{
+            self.assert_has_external_access()?;
+        }
+        if child.modify_self{
+            if same_address{
+                self.assert_has_self_modification()?;
+            }else{
+                self.assert_has_external_modification()?;
+            }
+        }
+        if child.access_self{
+            if same_address{
+                self.assert_has_self_access()?;
+            }else{
+                self.assert_has_external_access()?;
+            }
+        }
+        Ok(())
+    }
 }
 
 
@@ -126,6 +159,9 @@ pub struct ExecutionContext{
     /// The address which caused this execution to occur.
     /// This may be the sender of the transaction, or the smart contract which caused this execution to occur via a call.
 	pub sender: NeutronAddress,
+	/// The long form data of the sender, for address versions whose full form does not fit in a NeutronAddress.
+	/// Empty when the sender has no long form, such as when it is a smart contract
+	pub sender_long: Vec<u8>,
     /// The total amount of gas allowed to be consumed in this execution
 	pub gas_limit: u64,
 	/// The number of coins which were sent with this execution
@@ -133,6 +169,8 @@ pub struct ExecutionContext{
 	/// The address which caused this chain of execution to occur.
     /// This is the sender of the transaction which caused this execution.
 	pub origin: NeutronAddress,
+	/// The long form data of the origin. Empty when the origin has no long form
+	pub origin_long: Vec<u8>,
 	/// The current address of the executing smart contract
     pub self_address: NeutronAddress,
     pub execution_type: ExecutionType,
@@ -151,6 +189,24 @@ impl ExecutionContext{
         c.self_address.version = 2; //to match NARM VM number
         c
     }
+    /// The long address of the origin, encoded as its version followed by its long form data (or its short data if it has no long form)
+    pub fn origin_long_address(&self) -> Vec<u8>{
+        encode_long_address(&self.origin, &self.origin_long)
+    }
+    /// The long address of the sender, encoded as its version followed by its long form data (or its short data if it has no long form)
+    pub fn sender_long_address(&self) -> Vec<u8>{
+        encode_long_address(&self.sender, &self.sender_long)
+    }
+}
+
+fn encode_long_address(address: &NeutronAddress, long: &[u8]) -> Vec<u8>{
+    let mut v = address.version.to_le_bytes().to_vec();
+    if long.is_empty(){
+        v.extend_from_slice(&address.data);
+    }else{
+        v.extend_from_slice(long);
+    }
+    v
 }
 
 
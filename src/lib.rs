@@ -5,11 +5,19 @@ pub mod testbench;
 pub mod codata;
 pub mod neutronerror;
 pub mod narm_hypervisor;
+pub mod elf_loader;
+pub mod narm_validator;
 pub mod callsystem;
 pub mod vmmanager;
 pub mod manager;
+pub mod observer;
 pub mod harness;
 pub mod comap_abi_decoder;
+pub mod contract_address;
+pub mod scheduler;
+pub mod block_executor;
+#[cfg(test)]
+mod test_helpers;
 pub extern crate neutron_common as addressing;
 
 extern crate num;
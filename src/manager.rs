@@ -3,17 +3,106 @@ use crate::neutronerror::*;
 use crate::vmmanager::*;
 use crate::callsystem::*;
 use neutron_common::RecoverableError;
+use crate::observer::*;
+use crate::element_interfaces::logging::LogMessage;
+use crate::element_interfaces::events::{Event, Bloom};
+use crate::element_interfaces::storage::StateWrites;
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
 
+/// The result of an execution. For nested executions only gas_used and status are filled
+#[derive(Default, Debug)]
 pub struct NeutronResult{
     pub gas_used: u64,
-    pub status: u32
+    /// The status code of the contract, or the error code if the execution was reverted
+    pub status: u32,
+    /// If set, the execution failed with a recoverable error and all of its state changes were reverted
+    pub reverted: bool,
+    /// The output costack of the execution, with the top item last
+    pub output_stack: Vec<Vec<u8>>,
+    /// The output comap of the execution (ie, the result comap of the transaction)
+    pub output_map: HashMap<Vec<u8>, Vec<u8>>,
+    /// All log messages emitted during the execution
+    pub logs: Vec<LogMessage>,
+    /// The state writes committed by the execution
+    pub storage_writes: StateWrites,
+    /// The events emitted by the execution, excluding those of reverted contexts
+    pub events: Vec<Event>,
+    /// Bloom filter of the addresses and topics of the events
+    pub events_bloom: Bloom
+}
+
+/// The default maximum number of nested contexts, including the top level context
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 64;
+
+/// Determines how a nested call into a contract which is already executing further up the context stack is handled
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReentrancyPolicy{
+    /// Re-entry is always allowed
+    Allow,
+    /// Re-entry is never allowed
+    Deny,
+    /// Re-entry is only allowed when the re-entering context can not modify any state (ie, immutable and pure calls)
+    ReadOnly
+}
+
+impl Default for ReentrancyPolicy{
+    fn default() -> ReentrancyPolicy{
+        ReentrancyPolicy::Allow
+    }
 }
 
-#[derive(Default)]
 pub struct Manager{
+    /// Optional observer which is notified of execution events, such as entering contexts and element calls
+    pub observer: Option<Box<dyn ExecutionObserver>>,
+    /// The maximum number of nested contexts, including the top level context. Calls beyond this depth fail with a recoverable error
+    pub max_call_depth: usize,
+    /// How nested calls into a contract which is already executing are handled
+    pub reentrancy_policy: ReentrancyPolicy,
+    /// The number of events which had been emitted when each outstanding checkpoint was created
+    event_marks: Vec<usize>
+}
+
+impl Default for Manager{
+    fn default() -> Manager{
+        Manager{
+            observer: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            reentrancy_policy: ReentrancyPolicy::default(),
+            event_marks: vec![]
+        }
+    }
 }
 
 impl Manager{
+    /// Notifies the observer (if any) of an execution event
+    fn observe<F: FnOnce(&mut dyn ExecutionObserver)>(&mut self, f: F){
+        if let Some(o) = self.observer.as_mut(){
+            f(o.as_mut());
+        }
+    }
+    fn create_checkpoint(&mut self, codata: &mut CoData, callsystem: & CallSystem) -> Result<(), NeutronError>{
+        callsystem.global_storage.as_ref().unwrap().borrow_mut().create_checkpoint(codata)?;
+        //events are recorded along with state, so they are discarded when the checkpoint is reverted
+        self.event_marks.push(codata.events.len());
+        self.observe(|o| o.checkpoint_created(codata));
+        Ok(())
+    }
+    fn commit_checkpoint(&mut self, codata: &mut CoData, callsystem: & CallSystem) -> Result<(), NeutronError>{
+        self.event_marks.pop();
+        callsystem.global_storage.as_ref().unwrap().borrow_mut().commit_checkpoint(codata)?;
+        self.observe(|o| o.checkpoint_committed(codata));
+        Ok(())
+    }
+    fn revert_checkpoint(&mut self, codata: &mut CoData, callsystem: & CallSystem) -> Result<(), NeutronError>{
+        if let Some(mark) = self.event_marks.pop(){
+            codata.events.truncate(mark);
+        }
+        callsystem.global_storage.as_ref().unwrap().borrow_mut().revert_checkpoint(codata)?;
+        self.observe(|o| o.checkpoint_reverted(codata));
+        Ok(())
+    }
     /// Starts execution of a new Neutron instance, creating a new VM from the top context
     fn start_execution(&mut self, codata: &mut CoData, vmm: &VMManager) -> Result<Box<dyn VMHypervisor>, NeutronError>{
         assert!(codata.context_count() > 0);
@@ -30,8 +119,8 @@ impl Manager{
     /// Ends execution, pushing relevant execution results and destroying the top context
     fn end_execution(&mut self, codata: &mut CoData, _error: u32) -> Result<(), NeutronError>{
         codata.enter_element();
-        //gather and push call/execution results etc?
-
+        //keep the outputs of the finished execution so that they become the inputs of the caller
+        codata.move_input_to_output_costack();
         codata.exit_element();
         codata.pop_context()?;
         Ok(())
@@ -40,12 +129,14 @@ impl Manager{
     /// The VM is continually executed. Upon VM return, element calls will be handled, if a sub-contract is called, it'll cause a recursive execute() call. 
     /// The main loop will be exited either upon an unrecoverable error or upon the VM returning an "ended" result
     fn neutron_main_loop(&mut self, hypervisor: &mut Box<dyn VMHypervisor>, codata: &mut CoData, callsystem: & CallSystem, vmm: &VMManager) -> Result<VMResult, NeutronError>{
-        callsystem.global_storage.as_ref().unwrap().borrow_mut().create_checkpoint(codata)?;
         loop{
             let result = match hypervisor.execute(codata){
                 Ok(v) => v,
                 Err(e) => {
-                    dbg!(&e);
+                    self.observe(|o| o.error(codata, &e));
+                    if let Some(diagnostics) = hypervisor.diagnostics(){
+                        self.observe(|o| o.vm_fault(codata, &diagnostics));
+                    }
                     return Err(e);
                 }
             };
@@ -54,41 +145,46 @@ impl Manager{
                     return Ok(VMResult::Ended(v));
                 },
                 VMResult::ElementCall(element, function) => {
+                    self.observe(|o| o.before_element_call(codata, element, function));
                     codata.enter_element();
-                    match callsystem.call(codata, element, function){
+                    let call_result = callsystem.call(codata, element, function);
+                    codata.exit_element();
+                    self.observe(|o| o.after_element_call(codata, element, function, &call_result));
+                    match call_result{
                         Ok(v) => {
-                            codata.exit_element();
                             match v{
                                 ElementResult::Result(result) => {
                                     hypervisor.set_result(result);
                                 },
                                 ElementResult::NewCall => {
+                                    //the nested execution commits or reverts its own checkpoint and destroys its context
                                     match self.execute(codata, callsystem, vmm){
                                         Err(NeutronError::Recoverable(e)) => {
-                                            callsystem.global_storage.as_ref().unwrap().borrow_mut().revert_checkpoint(codata)?;
-                                            dbg!(&e);
+                                            hypervisor.set_error(e as u64);
+                                        },
+                                        Err(NeutronError::Host(e)) => {
                                             hypervisor.set_error(e as u64);
                                         },
                                         Err(NeutronError::Unrecoverable(e)) => {
-                                            dbg!(&e);
                                             return Err(NeutronError::Unrecoverable(e));
                                         },
-                                        Ok(_) =>{
-                                            callsystem.global_storage.as_ref().unwrap().borrow_mut().commit_checkpoint(codata)?;
+                                        Ok(result) =>{
+                                            hypervisor.set_result(result.status as u64);
                                         }
                                     }
                                 }
                             }
                         },
                         Err(e) => {
-                            codata.exit_element();
+                            self.observe(|o| o.error(codata, &e));
                             match e{
                                 NeutronError::Recoverable(v) => {
-                                    dbg!(&v);
+                                    hypervisor.set_error(v as u64);
+                                },
+                                NeutronError::Host(v) => {
                                     hypervisor.set_error(v as u64);
                                 },
                                 NeutronError::Unrecoverable(e) => {
-                                    dbg!(&e);
                                     return Err(NeutronError::Unrecoverable(e));
                                 }
                             }
@@ -99,53 +195,193 @@ impl Manager{
         }
     }
 
+    /// Executes the top context of the CoData
+    /// If a top level execution fails with a recoverable error, its state changes are reverted and it is reported as a reverted result
+    /// Nested executions run under their own gas limit, which is carved out of the caller's remaining gas. Unused gas is refunded to the caller upon return
     pub fn execute(&mut self, codata: &mut CoData, callsystem: & CallSystem, vmm: &VMManager) -> Result<NeutronResult, NeutronError>{
+        self.observe(|o| o.enter_context(codata));
+        let result = if codata.context_count() <= 1{
+            self.execute_context(codata, callsystem, vmm)
+        }else{
+            let caller_gas = codata.gas_remaining;
+            let gas_limit = cmp::min(codata.current_context().gas_limit, caller_gas);
+            codata.gas_remaining = gas_limit;
+            let result = self.execute_context(codata, callsystem, vmm);
+            codata.gas_remaining = (caller_gas - gas_limit) + codata.gas_remaining;
+            result
+        };
+        self.observe(|o| o.exit_context(codata, &result));
+        result
+    }
+
+    /// Nested executions which fail with a recoverable error are cleaned up (destroying their context) before the error is returned to the caller
+    fn execute_context(&mut self, codata: &mut CoData, callsystem: & CallSystem, vmm: &VMManager) -> Result<NeutronResult, NeutronError>{
         let original_gas = codata.gas_remaining;
-        let hv = &mut self.start_execution(codata, vmm)?;
-        hv.enter_state(codata, callsystem)?;
-        let mut error = 0;
-        match self.neutron_main_loop(hv, codata, callsystem, vmm){
+        let nested = codata.context_count() > 1;
+        if nested && codata.context_count() > self.max_call_depth{
+            return self.abort_execution(codata, NeutronError::Host(HostError::MaximumCallDepthExceeded), original_gas);
+        }
+        if nested && !self.reentrancy_allowed(codata){
+            return self.abort_execution(codata, NeutronError::Host(HostError::ReentrancyNotAllowed), original_gas);
+        }
+        let mut hv = match self.start_execution(codata, vmm){
+            Ok(v) => v,
+            Err(e) => {
+                return self.abort_execution(codata, e, original_gas);
+            }
+        };
+        //the checkpoint is created before entering state so that writes made while entering (ie, storing deployed code) are reverted on failure
+        self.create_checkpoint(codata, callsystem)?;
+        //coins sent with the execution are moved within its checkpoint, so that they are returned if the execution fails
+        let entered = match self.transfer_value_sent(codata, callsystem){
+            Ok(_) => hv.enter_state(codata, callsystem),
+            Err(e) => Err(e)
+        };
+        match entered{
+            Ok(_) => {},
+            Err(e) => {
+                self.revert_checkpoint(codata, callsystem)?;
+                //entering can run out of gas while charging for memory, which only fails a nested execution
+                let e = match e{
+                    NeutronError::Unrecoverable(UnrecoverableError::OutOfGas) if nested => NeutronError::Host(HostError::OutOfGas),
+                    e => e
+                };
+                return self.abort_execution(codata, e, original_gas);
+            }
+        }
+        let status = match self.neutron_main_loop(&mut hv, codata, callsystem, vmm){
             Ok(v) => {
                 match v{
                     VMResult::Ended(e) => {
-                        error = e;
+                        Ok(e)
                     },
                     VMResult::ElementCall(_, _) => {
-                        assert!(false, "Element call escaped Neutron execution loop. This should never happen");
+                        panic!("Element call escaped Neutron execution loop. This should never happen");
                     }
-                };
+                }
             },
             Err(e) => {
                 match e{
-                    NeutronError::Recoverable(e) => {
-                        if codata.context_count() == 1 {
-                            return Err(NeutronError::Unrecoverable(UnrecoverableError::TopLevelError(e)));
-                        }else{
-                            error = e as u32;
-                        }
+                    NeutronError::Recoverable(_) | NeutronError::Host(_) => {
+                        Err(e)
+                    },
+                    NeutronError::Unrecoverable(UnrecoverableError::OutOfGas) if nested => {
+                        //running out of gas only fails the nested execution, the caller can still use its own remaining gas
+                        Err(NeutronError::Host(HostError::OutOfGas))
                     },
                     NeutronError::Unrecoverable(e) => {
-                        //this leaves the entire structure in-tact for inspection
+                        //this leaves the entire structure in-tact for inspection, but storage is still reverted so that it is left balanced
+                        self.revert_checkpoint(codata, callsystem)?;
                         return Err(NeutronError::Unrecoverable(e));
                     }
-                };
+                }
             }
         };
+        let error = match &status{
+            Ok(v) => *v,
+            Err(e) => e.recoverable_code().unwrap()
+        };
         self.end_execution(codata, error)?;
         hv.exit_state(codata, callsystem)?;
+        let mut storage_writes = StateWrites::new();
+        match status{
+            Ok(_) => {
+                if !nested{
+                    storage_writes = callsystem.global_storage.as_ref().unwrap().borrow_mut().checkpoint_writes(codata)?;
+                }
+                self.commit_checkpoint(codata, callsystem)?;
+            },
+            Err(_) => self.revert_checkpoint(codata, callsystem)?
+        }
+
+        let mut result = NeutronResult::default();
+        result.gas_used = original_gas - codata.gas_remaining;
+        match status{
+            Ok(v) => {
+                result.status = v;
+            },
+            Err(e) => {
+                if nested{
+                    return Err(e);
+                }
+                //a failed top level execution is reported as reverted rather than as an error
+                result.status = e.recoverable_code().unwrap();
+                result.reverted = true;
+            }
+        }
+        if !nested{
+            result.output_stack = codata.input_stack().clone();
+            result.output_map = codata.result_map().clone();
+            result.logs = mem::take(&mut codata.logs);
+            result.storage_writes = storage_writes;
+            result.events = mem::take(&mut codata.events);
+            result.events_bloom = Bloom::from_events(&result.events);
+        }
+        Ok(result)
+    }
 
-        Ok(NeutronResult{
-            gas_used: original_gas - codata.gas_remaining,
-            status: error
-        })
+    /// Checks if the top context may execute according to the reentrancy policy
+    fn reentrancy_allowed(&self, codata: &CoData) -> bool{
+        let context = codata.current_context();
+        let reentrant = (1..codata.context_count()).any(|i| codata.peek_context(i).unwrap().self_address == context.self_address);
+        if !reentrant{
+            return true;
+        }
+        match self.reentrancy_policy{
+            ReentrancyPolicy::Allow => true,
+            ReentrancyPolicy::Deny => false,
+            ReentrancyPolicy::ReadOnly => !context.permissions.modify_self && !context.permissions.modify_external
+        }
+    }
+    /// Moves the coins sent with the top context from its sender to the executed contract
+    /// For nested executions this moves coins out of the calling contract and into another address, so the caller must be able to modify external state
+    fn transfer_value_sent(&mut self, codata: &mut CoData, callsystem: & CallSystem) -> Result<(), NeutronError>{
+        let context = codata.current_context().clone();
+        if context.value_sent == 0{
+            return Ok(());
+        }
+        if codata.context_count() > 1{
+            codata.peek_context(1)?.permissions.assert_has_external_modification()?;
+        }
+        callsystem.global_storage.as_ref().unwrap().borrow_mut().transfer_coins(codata, context.sender, context.self_address, context.value_sent)
+    }
+    /// Handles an error which occured before the VM could begin execution
+    /// Any checkpoint must already be reverted. A failed top level execution is reported as reverted, same as when the VM fails
+    fn abort_execution(&mut self, codata: &mut CoData, error: NeutronError, original_gas: u64) -> Result<NeutronResult, NeutronError>{
+        self.observe(|o| o.error(codata, &error));
+        match error.recoverable_code(){
+            Some(code) => {
+                //nothing was executed, so only the context needs to be destroyed
+                codata.pop_context()?;
+                if codata.context_count() > 0{
+                    return Err(error);
+                }
+                let mut result = NeutronResult::default();
+                //gas can be spent while entering state, such as for the memory used by the contract
+                result.gas_used = original_gas - codata.gas_remaining;
+                result.status = code;
+                result.reverted = true;
+                result.logs = mem::take(&mut codata.logs);
+                Ok(result)
+            },
+            None => {
+                Err(error)
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::cell::RefCell;
+        use std::cell::RefCell;
     use crate::{addressing::*, interface::ContextPermissions};
+    use crate::element_interfaces::call_contract::*;
+    use crate::element_interfaces::storage::*;
+    use crate::element_interfaces::logging::*;
+    use crate::element_interfaces::events::*;
+    use crate::db::MemoryGlobalState;
+    use crate::test_helpers::*;
 
     #[derive(Default)]
     struct TestVM{
@@ -365,9 +601,692 @@ mod tests {
 
         assert!(manager.execute(&mut codata, &callsystem, &vmm).is_err());
     }
-    
-}
 
+    /// Calls a sub-contract with the gas limit given in input key [20] (in units of 100), the sub-contract spends 600 gas
+    #[derive(Default)]
+    struct GasVM{
+        gas_before_call: u64,
+        result: Option<u64>,
+        error: Option<u64>
+    }
+    impl VMHypervisor for GasVM{
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError>{
+            if codata.context_count() > 1{
+                if codata.gas_remaining < 600{
+                    codata.gas_remaining = 0;
+                    return Err(NeutronError::Unrecoverable(UnrecoverableError::OutOfGas));
+                }
+                codata.gas_remaining -= 600;
+                return Ok(VMResult::Ended(0));
+            }
+            if self.result.is_none() && self.error.is_none(){
+                let gas_limit = codata.peek_input_key(&[20])?[0] as u64 * 100;
+                let call = call_test_contract(codata, 0, gas_limit)?;
+                self.gas_before_call = codata.gas_remaining;
+                return Ok(call);
+            }
+            match self.result{
+                Some(_) => {
+                    //unused gas is refunded
+                    assert_eq!(codata.gas_remaining, self.gas_before_call - 600);
+                    Ok(VMResult::Ended(1))
+                },
+                None => {
+                    //the sub-contract can only spend the gas it was given
+                    assert_eq!(self.error.unwrap(), HostError::OutOfGas as u64);
+                    assert_eq!(codata.gas_remaining, self.gas_before_call - 500);
+                    Ok(VMResult::Ended(2))
+                }
+            }
+        }
+        fn set_result(&mut self, code: u64){
+            self.result = Some(code);
+        }
+        fn set_error(&mut self, code: u64){
+            self.error = Some(code);
+        }
+        fn enter_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+    }
+
+    fn execute_gas_limited_call(gas_limit: u8) -> (NeutronResult, u64){
+        let mut codata = CoData::new();
+        codata.gas_remaining = 10000;
+        codata.push_output_key(&[20], &[gas_limit]).unwrap();
+        let mut callsystem = CallSystem::default();
+        let mut caller = ContractCaller::default();
+        callsystem.add_call(CALL_CONTRACT_FEATURE, &mut caller).unwrap();
+        let mut storage = TestStorageElement::default();
+        callsystem.global_storage = Some(RefCell::from(&mut storage));
+
+        let gasvm = || -> Box<dyn VMHypervisor>{
+            Box::from(GasVM::default())
+        };
+        let mut vmm = VMManager::default();
+        vmm.vm_builders.insert(1, gasvm);
+
+        let mut manager = Manager::default();
+        let mut context = crate::interface::ExecutionContext::default();
+        context.permissions = ContextPermissions::mutable_call();
+        context.self_address.version = 1;
+        context.gas_limit = 10000;
+        codata.push_context(context).unwrap();
+
+        let result = manager.execute(&mut codata, &callsystem, &vmm).unwrap();
+        assert_eq!(codata.context_count(), 0);
+        (result, codata.gas_remaining)
+    }
+
+    #[test]
+    fn test_nested_gas_limit_refund(){
+        let (result, gas_remaining) = execute_gas_limited_call(10);
+        assert_eq!(result.status, 1);
+        assert_eq!(result.gas_used, 600);
+        assert_eq!(gas_remaining, 10000 - 600);
+    }
+
+    #[test]
+    fn test_nested_gas_limit_out_of_gas(){
+        let (result, gas_remaining) = execute_gas_limited_call(5);
+        assert_eq!(result.status, 2);
+        assert_eq!(result.gas_used, 500);
+        assert_eq!(gas_remaining, 10000 - 500);
+    }
+
+    /// Stores a value, logs a message and then outputs data. Reverts if input key [30] is set to 1
+    #[derive(Default)]
+    struct ResultVM{
+        step: u32
+    }
+    impl VMHypervisor for ResultVM{
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError>{
+            self.step += 1;
+            match self.step{
+                1 => {
+                    codata.push_output_stack(&[7])?;
+                    codata.push_output_stack(&[1])?;
+                    Ok(VMResult::ElementCall(GLOBAL_STORAGE_FEATURE, GlobalStateFunctions::StoreState as u32))
+                },
+                2 => {
+                    codata.push_output_stack("hello".as_bytes())?;
+                    codata.push_output_stack(&[1])?;
+                    Ok(VMResult::ElementCall(LOGGING_FEATURE, LoggingFunctions::LogInfo as u32))
+                },
+                _ => {
+                    codata.push_output_stack(&[9])?;
+                    codata.push_output_key(&[5], &[6])?;
+                    if codata.peek_input_key(&[30]).unwrap_or(vec![0]) == vec![1]{
+                        return Err(NeutronError::Recoverable(RecoverableError::ContractRevertedExecution));
+                    }
+                    Ok(VMResult::Ended(3))
+                }
+            }
+        }
+        fn set_result(&mut self, _code: u64){
+        }
+        fn set_error(&mut self, _code: u64){
+            panic!("element call should not fail");
+        }
+        fn enter_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+    }
+
+    fn execute_result_vm(revert: bool) -> (NeutronResult, MemoryGlobalState){
+        execute_result_vm_with_value(revert, 0, 0)
+    }
+
+    /// The sender of the execution is given `sender_balance` coins before sending `value` of them to the ResultVM contract
+    fn execute_result_vm_with_value(revert: bool, sender_balance: u64, value: u64) -> (NeutronResult, MemoryGlobalState){
+        let mut codata = CoData::new();
+        codata.push_output_key(&[30], &[revert as u8]).unwrap();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        db.set_balance(&mut codata, sender_address(), sender_balance).unwrap();
+        let mut logger = StdoutLogger::default();
+        let result = {
+            let mut callsystem = CallSystem::default();
+            callsystem.global_storage = Some(RefCell::new(&mut db));
+            callsystem.logging = Some(RefCell::new(&mut logger));
+
+            let resultvm = || -> Box<dyn VMHypervisor>{
+                Box::from(ResultVM::default())
+            };
+            let mut vmm = VMManager::default();
+            vmm.vm_builders.insert(1, resultvm);
+
+            let mut manager = Manager::default();
+            let mut context = crate::interface::ExecutionContext::default();
+            context.permissions = ContextPermissions::mutable_call();
+            context.self_address.version = 1;
+            context.sender = sender_address();
+            context.value_sent = value;
+            codata.push_context(context).unwrap();
+
+            manager.execute(&mut codata, &callsystem, &vmm).unwrap()
+        };
+        assert_eq!(codata.context_count(), 0);
+        (result, db)
+    }
+
+    #[test]
+    fn test_result_contents(){
+        let (result, _) = execute_result_vm(false);
+        let mut address = NeutronAddress::default();
+        address.version = 1;
+        assert_eq!(result.status, 3);
+        assert!(!result.reverted);
+        assert_eq!(result.output_stack, vec![vec![9]]);
+        assert_eq!(result.output_map.get(&vec![5]).unwrap(), &vec![6]);
+        assert_eq!(result.logs.len(), 1);
+        assert_eq!(result.logs[0].level, LoggingFunctions::LogInfo);
+        assert_eq!(result.logs[0].message, "hello");
+        assert_eq!(result.storage_writes.get(&address).unwrap().get(&vec![95, 1]).unwrap(), &vec![7]);
+    }
+
+    #[test]
+    fn test_result_reverted(){
+        let (result, mut db) = execute_result_vm(true);
+        let mut address = NeutronAddress::default();
+        address.version = 1;
+        assert!(result.reverted);
+        assert_eq!(result.status, RecoverableError::ContractRevertedExecution as u32);
+        assert!(result.storage_writes.is_empty());
+        assert!(db.read_key(&address, &[95, 1]).is_err());
+    }
+
+    fn sender_address() -> NeutronAddress{
+        let mut address = NeutronAddress::default();
+        address.data[0] = 1;
+        address
+    }
+
+    #[test]
+    fn test_value_sent(){
+        let mut address = NeutronAddress::default();
+        address.version = 1;
+        let mut codata = CoData::new();
+
+        let (result, mut db) = execute_result_vm_with_value(false, 150, 100);
+        assert!(!result.reverted);
+        assert_eq!(db.get_balance(&mut codata, sender_address()).unwrap(), 50);
+        assert_eq!(db.get_balance(&mut codata, address).unwrap(), 100);
+        assert!(result.storage_writes.get(&address).unwrap().contains_key(&BALANCE_KEY.to_vec()));
+
+        //coins are returned when the execution fails
+        let (result, mut db) = execute_result_vm_with_value(true, 150, 100);
+        assert!(result.reverted);
+        assert_eq!(db.get_balance(&mut codata, sender_address()).unwrap(), 150);
+        assert_eq!(db.get_balance(&mut codata, address).unwrap(), 0);
+
+        let (result, mut db) = execute_result_vm_with_value(false, 50, 100);
+        assert!(result.reverted);
+        assert_eq!(result.status, RecoverableError::LowTokenBalance as u32);
+        assert_eq!(db.get_balance(&mut codata, sender_address()).unwrap(), 50);
+        assert!(db.read_key(&address, &[95, 1]).is_err());
+    }
+
+    /// Emits an event with its nest level as topic and calls itself once. The nested call reverts after emitting its event, then
+    /// the top level emits a second event and reverts if input key [30] is set to 1
+    #[derive(Default)]
+    struct EventVM{
+        step: u32
+    }
+    impl VMHypervisor for EventVM{
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError>{
+            self.step += 1;
+            match self.step{
+                1 => {
+                    codata.push_output_stack(&[codata.context_count() as u8])?;
+                    codata.push_output_stack(&[1, 2, 3])?;
+                    Ok(VMResult::ElementCall(EVENTS_FEATURE, EventsFunctions::EmitEvent as u32))
+                },
+                2 if codata.context_count() > 1 => {
+                    Err(NeutronError::Recoverable(RecoverableError::ContractRevertedExecution))
+                },
+                2 => {
+                    call_test_contract(codata, 0, 1000)
+                },
+                3 => {
+                    codata.push_output_stack(&[9])?;
+                    codata.push_output_stack(&[4])?;
+                    Ok(VMResult::ElementCall(EVENTS_FEATURE, EventsFunctions::EmitEvent as u32))
+                },
+                _ => {
+                    if codata.peek_input_key(&[30]).unwrap_or(vec![0]) == vec![1]{
+                        return Err(NeutronError::Recoverable(RecoverableError::ContractRevertedExecution));
+                    }
+                    Ok(VMResult::Ended(0))
+                }
+            }
+        }
+        fn set_result(&mut self, _code: u64){
+        }
+        fn set_error(&mut self, _code: u64){
+        }
+        fn enter_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+    }
+
+    fn execute_event_vm(revert: bool) -> NeutronResult{
+        let mut codata = CoData::new();
+        codata.gas_remaining = 10000;
+        codata.push_output_key(&[30], &[revert as u8]).unwrap();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut caller = ContractCaller::default();
+        let mut emitter = EventEmitter::default();
+        let mut callsystem = CallSystem::default();
+        callsystem.add_call(CALL_CONTRACT_FEATURE, &mut caller).unwrap();
+        callsystem.add_call(EVENTS_FEATURE, &mut emitter).unwrap();
+        callsystem.global_storage = Some(RefCell::new(&mut db));
+
+        let eventvm = || -> Box<dyn VMHypervisor>{
+            Box::from(EventVM::default())
+        };
+        let mut vmm = VMManager::default();
+        vmm.vm_builders.insert(1, eventvm);
+
+        let mut manager = Manager::default();
+        let mut context = crate::interface::ExecutionContext::default();
+        context.permissions = ContextPermissions::mutable_call();
+        context.self_address.version = 1;
+        context.gas_limit = 10000;
+        codata.push_context(context).unwrap();
+
+        let result = manager.execute(&mut codata, &callsystem, &vmm).unwrap();
+        assert_eq!(codata.context_count(), 0);
+        assert!(codata.events.is_empty());
+        result
+    }
+
+    #[test]
+    fn test_events(){
+        let result = execute_event_vm(false);
+        let mut address = NeutronAddress::default();
+        address.version = 1;
+        assert!(!result.reverted);
+        //the event of the reverted nested call is discarded
+        assert_eq!(result.events.len(), 2);
+        assert_eq!(result.events[0].address, address);
+        assert_eq!(result.events[0].topics, vec![vec![1]]);
+        assert_eq!(result.events[0].data, vec![1, 2, 3]);
+        assert_eq!(result.events[1].topics, vec![vec![9]]);
+        assert_eq!(result.events[1].data, vec![4]);
+        assert!(result.events_bloom.contains(&[1]));
+        assert!(result.events_bloom.contains(&[9]));
+
+        let result = execute_event_vm(true);
+        assert!(result.reverted);
+        assert!(result.events.is_empty());
+        assert_eq!(result.events_bloom, Bloom::default());
+    }
+
+    /// Calls itself until the call fails, each level ends with the number of successful calls below it
+    #[derive(Default)]
+    struct RecursiveVM{
+        result: Option<u64>,
+        errored: bool
+    }
+    impl VMHypervisor for RecursiveVM{
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError>{
+            if self.errored{
+                return Ok(VMResult::Ended(0));
+            }
+            match self.result{
+                None => {
+                    call_test_contract(codata, 0, 1000)
+                },
+                Some(v) => {
+                    Ok(VMResult::Ended(v as u32 + 1))
+                }
+            }
+        }
+        fn set_result(&mut self, code: u64){
+            self.result = Some(code);
+        }
+        fn set_error(&mut self, _code: u64){
+            self.errored = true;
+        }
+        fn enter_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+    }
+
+    /// Tracks the number of outstanding checkpoints
+    #[derive(Default)]
+    struct CheckpointCounter{
+        checkpoints: i32
+    }
+    impl GlobalState for CheckpointCounter{
+        fn store_state(&mut self, _codata: &mut CoData, _key: &[u8], _value: &[u8]) -> Result<(), NeutronError>{Ok(())}
+        fn load_state(&mut self, _codata: &mut CoData, _key: &[u8]) -> Result<Vec<u8>, NeutronError>{Ok(vec![0])}
+        fn key_exists(&mut self, _codata: &mut CoData, _key: &[u8]) -> Result<bool, NeutronError>{Ok(false)}
+
+        fn private_store_state(&mut self, _codata: &mut CoData, _key: &[u8], _value: &[u8]) -> Result<(), NeutronError>{Ok(())}
+        fn private_load_state(&mut self, _codata: &mut CoData, _key: &[u8]) -> Result<Vec<u8>, NeutronError>{Ok(vec![0])}
+
+        fn private_store_state_external(&mut self, _codata: &mut CoData, _address: NeutronAddress, _key: &[u8], _value: &[u8]) -> Result<(), NeutronError> {Ok(())}
+        fn private_load_state_external(&mut self, _codata: &mut CoData, _address: NeutronAddress, _key: &[u8]) -> Result<Vec<u8>, NeutronError> {Ok(vec![0])}
+
+        fn create_checkpoint(&mut self, _codata: &mut CoData) -> Result<(), NeutronError>{
+            self.checkpoints += 1;
+            Ok(())
+        }
+        fn revert_checkpoint(&mut self, _codata: &mut CoData) -> Result<(), NeutronError>{
+            self.checkpoints -= 1;
+            Ok(())
+        }
+        fn commit_checkpoint(&mut self, _codata: &mut CoData) -> Result<(), NeutronError>{
+            self.checkpoints -= 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_unrecoverable_error_balances_checkpoints(){
+        let mut codata = CoData::new();
+        codata.push_output_key(&[10], &[3]).unwrap();
+        let mut element = TestElement::default();
+        let mut storage = CheckpointCounter::default();
+        {
+            let mut callsystem = CallSystem::default();
+            callsystem.add_call(1, &mut element).unwrap();
+            callsystem.global_storage = Some(RefCell::from(&mut storage as &mut dyn GlobalState));
+
+            let testvm = || -> Box<dyn VMHypervisor>{
+                Box::from(TestVM::default())
+            };
+            let mut vmm = VMManager::default();
+            vmm.vm_builders.insert(1, testvm);
+
+            let mut manager = Manager::default();
+            let mut context = crate::interface::ExecutionContext::default();
+            context.permissions = ContextPermissions::mutable_call();
+            context.self_address.version = 1;
+            codata.push_context(context).unwrap();
+
+            assert!(manager.execute(&mut codata, &callsystem, &vmm).is_err());
+        }
+        assert_eq!(storage.checkpoints, 0);
+        //the context structure is left intact for inspection
+        assert_eq!(codata.context_count(), 2);
+    }
+
+    /// Spends 100 gas and stores code while entering state, then fails
+    #[derive(Default)]
+    struct FailingDeployVM{
+    }
+    impl VMHypervisor for FailingDeployVM{
+        fn execute(&mut self, _codata: &mut CoData) -> Result<VMResult, NeutronError>{
+            panic!("execution should not begin");
+        }
+        fn set_result(&mut self, _code: u64){
+        }
+        fn set_error(&mut self, _code: u64){
+        }
+        fn enter_state(&mut self, codata: &mut CoData, callsystem: & CallSystem) -> Result<(), NeutronError>{
+            codata.charge_gas(100)?;
+            callsystem.global_storage.as_ref().unwrap().borrow_mut().private_store_state(codata, &[0x02, 0], &[1, 2, 3])?;
+            Err(NeutronError::Recoverable(RecoverableError::ContractExecutionError))
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_enter_state_reverts_writes(){
+        let mut codata = CoData::new();
+        codata.gas_remaining = 1000;
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let result = {
+            let mut callsystem = CallSystem::default();
+            callsystem.global_storage = Some(RefCell::new(&mut db));
+
+            let failingvm = || -> Box<dyn VMHypervisor>{
+                Box::from(FailingDeployVM::default())
+            };
+            let mut vmm = VMManager::default();
+            vmm.vm_builders.insert(1, failingvm);
+
+            let mut manager = Manager::default();
+            let mut context = crate::interface::ExecutionContext::default();
+            context.permissions = ContextPermissions::mutable_call();
+            context.self_address.version = 1;
+            codata.push_context(context).unwrap();
+
+            manager.execute(&mut codata, &callsystem, &vmm).unwrap()
+        };
+        assert!(result.reverted);
+        assert_eq!(result.status, RecoverableError::ContractExecutionError as u32);
+        assert_eq!(result.gas_used, 100);
+        assert_eq!(codata.context_count(), 0);
+        let mut address = NeutronAddress::default();
+        address.version = 1;
+        assert!(db.read_key(&address, &[0x02, 0]).is_err());
+        //only the checkpoint created before execution is left
+        assert!(db.revert_single_checkpoint().is_ok());
+        assert!(db.revert_single_checkpoint().is_err());
+    }
+
+    #[derive(Default)]
+    struct ErrorObserver{
+        errors: std::rc::Rc<RefCell<Vec<NeutronError>>>
+    }
+    impl ExecutionObserver for ErrorObserver{
+        fn error(&mut self, _codata: &CoData, error: &NeutronError){
+            self.errors.borrow_mut().push(error.clone());
+        }
+    }
+
+    fn execute_recursive_call(manager: &mut Manager, permissions: ContextPermissions) -> (NeutronResult, Vec<NeutronError>){
+        let mut codata = CoData::new();
+        codata.gas_remaining = 10000;
+        let mut callsystem = CallSystem::default();
+        let mut caller = ContractCaller::default();
+        callsystem.add_call(CALL_CONTRACT_FEATURE, &mut caller).unwrap();
+        let mut storage = TestStorageElement::default();
+        callsystem.global_storage = Some(RefCell::from(&mut storage));
+
+        let recursivevm = || -> Box<dyn VMHypervisor>{
+            Box::from(RecursiveVM::default())
+        };
+        let mut vmm = VMManager::default();
+        vmm.vm_builders.insert(1, recursivevm);
+
+        let observer = ErrorObserver::default();
+        let errors = observer.errors.clone();
+        manager.observer = Some(Box::from(observer));
+        let mut context = crate::interface::ExecutionContext::default();
+        context.permissions = permissions;
+        context.self_address.version = 1;
+        context.gas_limit = 10000;
+        codata.push_context(context).unwrap();
+
+        let result = manager.execute(&mut codata, &callsystem, &vmm).unwrap();
+        assert!(!result.reverted);
+        assert_eq!(codata.context_count(), 0);
+        let errors = errors.borrow().clone();
+        (result, errors)
+    }
+
+    #[test]
+    fn test_max_call_depth(){
+        let mut manager = Manager::default();
+        manager.max_call_depth = 4;
+        //contexts 2, 3 and 4 are created, the call from context 4 fails
+        let (result, errors) = execute_recursive_call(&mut manager, ContextPermissions::mutable_call());
+        assert_eq!(result.status, 3);
+        assert_eq!(errors, vec![NeutronError::Host(HostError::MaximumCallDepthExceeded)]);
+    }
+
+    #[test]
+    fn test_reentrancy_denied(){
+        let mut manager = Manager::default();
+        manager.reentrancy_policy = ReentrancyPolicy::Deny;
+        let (result, errors) = execute_recursive_call(&mut manager, ContextPermissions::mutable_call());
+        assert_eq!(result.status, 0);
+        assert_eq!(errors, vec![NeutronError::Host(HostError::ReentrancyNotAllowed)]);
+    }
+
+    #[test]
+    fn test_reentrancy_read_only(){
+        let mut manager = Manager::default();
+        manager.max_call_depth = 4;
+        manager.reentrancy_policy = ReentrancyPolicy::ReadOnly;
+        let (result, errors) = execute_recursive_call(&mut manager, ContextPermissions::mutable_call());
+        assert_eq!(result.status, 0);
+        assert_eq!(errors, vec![NeutronError::Host(HostError::ReentrancyNotAllowed)]);
+
+        //immutable calls can not modify state, so re-entry is allowed until the depth limit
+        let (result, errors) = execute_recursive_call(&mut manager, ContextPermissions::immutable_call());
+        assert_eq!(result.status, 3);
+        assert_eq!(errors, vec![NeutronError::Host(HostError::MaximumCallDepthExceeded)]);
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver{
+        events: std::rc::Rc<RefCell<Vec<String>>>
+    }
+    impl ExecutionObserver for RecordingObserver{
+        fn enter_context(&mut self, codata: &CoData){
+            self.events.borrow_mut().push(format!("enter {}", codata.context_count()));
+        }
+        fn exit_context(&mut self, codata: &CoData, result: &Result<NeutronResult, NeutronError>){
+            self.events.borrow_mut().push(format!("exit {} {}", codata.context_count(), result.is_ok()));
+        }
+        fn before_element_call(&mut self, _codata: &CoData, element: u32, function: u32){
+            self.events.borrow_mut().push(format!("before {} {}", element, function));
+        }
+        fn after_element_call(&mut self, _codata: &CoData, element: u32, function: u32, result: &Result<ElementResult, NeutronError>){
+            self.events.borrow_mut().push(format!("after {} {} {}", element, function, result.is_ok()));
+        }
+        fn checkpoint_created(&mut self, _codata: &CoData){
+            self.events.borrow_mut().push("checkpoint".to_string());
+        }
+        fn checkpoint_committed(&mut self, _codata: &CoData){
+            self.events.borrow_mut().push("commit".to_string());
+        }
+        fn checkpoint_reverted(&mut self, _codata: &CoData){
+            self.events.borrow_mut().push("revert".to_string());
+        }
+        fn error(&mut self, _codata: &CoData, error: &NeutronError){
+            self.events.borrow_mut().push(format!("error {:?}", error));
+        }
+        fn vm_fault(&mut self, _codata: &CoData, diagnostics: &str){
+            self.events.borrow_mut().push(format!("fault {}", diagnostics));
+        }
+    }
+
+    #[test]
+    fn test_observer_events(){
+        let mut codata = CoData::new();
+        codata.push_output_key(&[10], &[1]).unwrap();
+        let mut callsystem = CallSystem::default();
+        let mut element = TestElement::default();
+        callsystem.add_call(1, &mut element).unwrap();
+        let mut storage = TestStorageElement::default();
+        callsystem.global_storage = Some(RefCell::from(&mut storage));
+
+        let testvm = || -> Box<dyn VMHypervisor>{
+            Box::from(TestVM::default())
+        };
+        let mut vmm = VMManager::default();
+        vmm.vm_builders.insert(1, testvm);
+
+        let observer = RecordingObserver::default();
+        let events = observer.events.clone();
+        let mut manager = Manager::default();
+        manager.observer = Some(Box::from(observer));
+        let mut context = crate::interface::ExecutionContext::default();
+        context.permissions = ContextPermissions::mutable_call();
+        context.self_address.version = 1;
+        codata.push_context(context).unwrap();
+
+        manager.execute(&mut codata, &callsystem, &vmm).unwrap();
+        assert_eq!(*events.borrow(), vec![
+            "enter 1", "checkpoint", "before 1 2", "after 1 2 true",
+            "enter 2", "checkpoint", "commit", "exit 1 true",
+            "commit", "exit 0 true"
+        ]);
+    }
+
+    /// Makes an element call, then faults
+    #[derive(Default)]
+    struct FaultingVM{
+        called: bool
+    }
+    impl VMHypervisor for FaultingVM{
+        fn execute(&mut self, _codata: &mut CoData) -> Result<VMResult, NeutronError>{
+            if !self.called{
+                self.called = true;
+                return Ok(VMResult::ElementCall(1, 0));
+            }
+            Err(NeutronError::Recoverable(RecoverableError::ContractExecutionError))
+        }
+        fn set_result(&mut self, _code: u64){}
+        fn set_error(&mut self, _code: u64){}
+        fn enter_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: & CallSystem) -> Result<(), NeutronError>{
+            Ok(())
+        }
+        fn diagnostics(&self) -> Option<String>{
+            Some("pc=0x10".to_string())
+        }
+    }
+
+    #[test]
+    fn test_observer_vm_fault(){
+        let mut codata = CoData::new();
+        let mut callsystem = CallSystem::default();
+        let mut element = TestElement::default();
+        callsystem.add_call(1, &mut element).unwrap();
+        let mut storage = TestStorageElement::default();
+        callsystem.global_storage = Some(RefCell::from(&mut storage));
+
+        let faultingvm = || -> Box<dyn VMHypervisor>{
+            Box::from(FaultingVM::default())
+        };
+        let mut vmm = VMManager::default();
+        vmm.vm_builders.insert(1, faultingvm);
+
+        let observer = RecordingObserver::default();
+        let events = observer.events.clone();
+        let mut manager = Manager::default();
+        manager.observer = Some(Box::from(observer));
+        let mut context = crate::interface::ExecutionContext::default();
+        context.permissions = ContextPermissions::mutable_call();
+        context.self_address.version = 1;
+        codata.push_context(context).unwrap();
+
+        let result = manager.execute(&mut codata, &callsystem, &vmm).unwrap();
+        assert!(result.reverted);
+        //the diagnostics of the VM follow the error it raised, and the checkpoint is reverted before the context exits
+        assert_eq!(*events.borrow(), vec![
+            "enter 1", "checkpoint", "before 1 0", "after 1 0 true",
+            "error Recoverable(ContractExecutionError)", "fault pc=0x10",
+            "revert", "exit 0 true"
+        ]);
+    }
+}
 
 
 
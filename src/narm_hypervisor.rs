@@ -1,9 +1,11 @@
 use crate::callsystem::*;
 use crate::codata::*;
 use crate::comap_abi_decoder::*;
+use crate::element_interfaces::upgrade::*;
 use crate::interface::*;
 use crate::narm::narmvm::*;
 use crate::narm::*;
+use crate::narm_validator::*;
 use crate::neutronerror::*;
 use crate::vmmanager::*;
 use neutron_common::RecoverableError;
@@ -13,14 +15,96 @@ use std::cmp;
 
 For documentation see https://neutron.earlgrey.tech/spec/neutron-arm-vm
 
+The SVC numbers below are specific to NARM and do not follow the interrupt numbers of the x86 hypervisor, which were already different
+for functions such as move_input_to_output_costack (0x16) and gas_remaining (0x90). Because of this, context functions added later, such
+as gas_limit, value_sent and nest_level, are numbered after the existing ones. neutron-star must use this table for NARM.
+Arguments are given in r0-r3 and results returned in r0 (with the top half of u64 results in r1). Items marked "stack" are on the costack
+
+-- Costack functions
+SVC 0x10: push_costack(buffer: pointer, size: u32)
+SVC 0x11: pop_costack(buffer: pointer, max_size: u32) -> actual_size: u32
+SVC 0x12: peek_costack(buffer: pointer, max_size: u32, index: u32) -> actual_size: u32
+SVC 0x13: swap_costack(index: u32)
+SVC 0x14: clear_costack()
+SVC 0x15: dup_costack(index: u32)
+SVC 0x16: move_input_to_output_costack()
+SVC 0x17: costack_count() -> count: u32
+SVC 0x18: costack_size() -> size: u32
+SVC 0x19: costack_remaining() -> size: u32
+
+-- Element functions
+SVC 0x20: element_call(element: u32, function: u32) -> result: u64 -- errors are returned with the top bit of r0 set
+
+-- Comap functions
+SVC 0x30: push_comap(key: stack [u8], abi_data: u32, value: stack [u8])
+SVC 0x31: push_raw_comap(key: stack [u8], raw_value: stack [u8])
+SVC 0x32: peek_comap(key: stack [u8], begin: u32, max_length: u32) -> (abi_data: u32, value: stack [u8])
+SVC 0x33: peek_raw_comap(key: stack [u8], begin: u32, max_length: u32) -> raw_value: stack [u8]
+SVC 0x34: peek_result_comap(key: stack [u8], begin: u32, max_length: u32) -> (abi_data: u32, value: stack [u8])
+SVC 0x35: peek_raw_result_comap(key: stack [u8], begin: u32, max_length: u32) -> raw_value: stack [u8]
+
+-- Hypervisor functions
+SVC 0x80: alloc_memory(size: u32) -> address: u32
+
+-- Context functions
+SVC 0x90: gas_remaining() -> gas: u64
+SVC 0x91: self_address() -> address: stack NeutronAddress
+SVC 0x92: origin() -> address: stack NeutronAddress
+SVC 0x93: origin_long() -> address: stack NeutronLongAddress
+SVC 0x94: sender() -> address: stack NeutronAddress
+SVC 0x95: sender_long() -> address: stack NeutronLongAddress
+SVC 0x96: execution_type() -> type: u32
+SVC 0x97: gas_limit() -> limit: u64
+SVC 0x98: value_sent() -> value: u64
+SVC 0x99: nest_level() -> level: u32
+SVC 0x9A: permissions() -> flags: u32
+
+-- System functions
+SVC 0xFE: revert_execution() -> noreturn
+SVC 0xFF: exit_execution(status: u32) -> noreturn
+
 */
 
+/// The address at which the read-only code of a contract is mapped
+pub const CODE_ADDRESS: u32 = 0x1_0000;
+/// The end (exclusive) of the region the code of a contract can occupy
+pub const CODE_REGION_END: u32 = 0x8000_0000;
+/// The address at which the writeable data of a contract is mapped
+pub const DATA_ADDRESS: u32 = 0x8001_0000;
+/// The end (exclusive) of the region the data of a contract can occupy
+pub const DATA_REGION_END: u32 = STACK_ADDRESS;
+/// The address at which the stack is mapped
+pub const STACK_ADDRESS: u32 = 0x8100_0000;
+/// The address at which memory allocated with alloc_memory begins
+pub const HEAP_ADDRESS: u32 = 0x8200_0000;
+/// The size of the stack memory mapped at STACK_ADDRESS
+pub const STACK_SIZE: u32 = 0xFFFF;
+/// The maximum total size of the memory which can be allocated with alloc_memory
+pub const MAX_HEAP_SIZE: u32 = 0x0100_0000;
+/// Protected storage key holding the entry point of a contract
+pub const CONTRACT_ENTRY_KEY: [u8; 2] = [0x02, 0x30];
+
+/// Decodes an entry point as given in the "!.e" comap key. A missing entry point is the start of the code, in Thumb mode
+pub fn decode_contract_entry(entry: Option<Vec<u8>>) -> u32 {
+    match entry {
+        Some(v) if v.len() >= 4 => u32::from_le_bytes([v[0], v[1], v[2], v[3]]),
+        _ => CODE_ADDRESS | 1,
+    }
+}
+
 #[derive(Default)]
 pub struct NarmHypervisor {
     vm: NarmVM,
-    errored: bool,
     result: Option<u64>,
     error: Option<u64>,
+    /// The total size of the memory allocated with alloc_memory
+    heap_size: u32,
+    /// The writeable memory (data, stack and heap) accounted for in the CoData
+    writeable_memory: u32,
+    /// The read-only memory (code) accounted for in the CoData
+    read_only_memory: u32,
+    /// The diagnostics of the VM when it last faulted
+    fault_diagnostics: Option<String>,
 }
 
 enum HypervisorState {
@@ -50,8 +134,10 @@ impl NarmHypervisor {
         }
         loop {
             self.vm.gas_remaining = codata.gas_remaining;
-            let syscall = self.vm.execute()?;
+            let syscall = self.vm.execute();
+            //gas must be synced even when the VM errors, so that gas spent before the error is accounted for
             codata.gas_remaining = self.vm.gas_remaining;
+            let syscall = syscall?;
             match syscall {
                 //***************************//
                 //**   Costack operators   **//
@@ -88,16 +174,111 @@ impl NarmHypervisor {
                     }
                 }
 
+                //SVC 0x12: peek_costack (buffer: pointer, max_size: u32, index: u32) -> actual_size: u32
+                //Like pop_costack, but copies the item `index` items below the top of the costack without removing it
+                0x12 => {
+                    let address = self.vm.external_get_reg(0);
+                    let max_size = self.vm.external_get_reg(1);
+                    let index = self.vm.external_get_reg(2);
+                    let data = match codata.peek_input_stack(index) {
+                        Ok(d) => d,
+                        Err(e) => {
+                            return Ok(HypervisorState::Error(e));
+                        }
+                    };
+                    self.vm.external_set_reg(0, data.len() as u32);
+                    if max_size != 0 {
+                        self.vm
+                            .copy_into_memory(address, &data[0..(cmp::min(data.len(), max_size as usize))])?;
+                    }
+                }
+
+                //SVC 0x13: swap_costack (index: u32)
+                //Swaps the top item of the costack with the item `index` items below it
+                0x13 => {
+                    let index = self.vm.external_get_reg(0);
+                    match codata.swap_input_stack(index) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            return Ok(HypervisorState::Error(e));
+                        }
+                    }
+                }
+
                 //SVC 0x14: clear_costack()
                 0x14 => {
                     codata.clear_input_stack();
                 }
 
+                //SVC 0x15: dup_costack (index: u32)
+                //Pushes a copy of the input costack item `index` items below the top to the output costack
+                0x15 => {
+                    let index = self.vm.external_get_reg(0);
+                    match codata.dup_input_stack(index) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            return Ok(HypervisorState::Error(e));
+                        }
+                    }
+                }
+
                 //SVC 0x16: move_input_to_output_costack()
                 0x16 => {
                     codata.move_input_to_output_costack();
                 }
 
+                //SVC 0x17: costack_count() -> count: u32
+                //Get the number of items on the input costack
+                0x17 => {
+                    self.vm.external_set_reg(0, codata.input_stack().len() as u32);
+                }
+
+                //SVC 0x18: costack_size() -> size: u32
+                //Get the total size in bytes of the items on the input costack
+                0x18 => {
+                    self.vm.external_set_reg(0, codata.input_stack_size() as u32);
+                }
+
+                //SVC 0x19: costack_remaining() -> size: u32
+                //Get the number of bytes which can still be pushed to the output costack
+                0x19 => {
+                    let remaining = cmp::min(codata.output_stack_remaining(), u32::MAX as u64);
+                    self.vm.external_set_reg(0, remaining as u32);
+                }
+
+                //*****************************//
+                //**   Hypervisor operators   **//
+                //*****************************//
+
+                //SVC 0x80: alloc_memory(size: u32) -> address: u32
+                //Maps a new block of writeable memory of at least size bytes directly after the previously allocated block and returns its address
+                //Accesses can not span multiple blocks. 0 is returned if the heap limit would be exceeded
+                0x80 => {
+                    let size = self.vm.external_get_reg(0);
+                    let address = HEAP_ADDRESS + self.heap_size;
+                    //blocks are kept 8 byte aligned
+                    let size = match size.checked_add(7) {
+                        Some(s) => s & !7,
+                        None => u32::MAX,
+                    };
+                    if size > MAX_HEAP_SIZE - self.heap_size {
+                        self.vm.external_set_reg(0, 0);
+                    } else {
+                        if size > 0 {
+                            match codata.allocate_writeable_memory(size) {
+                                Ok(_) => {}
+                                Err(e) => {
+                                    return Ok(HypervisorState::Error(e));
+                                }
+                            }
+                            self.writeable_memory += size;
+                            self.vm.memory.add_memory(address, size)?;
+                            self.heap_size += size;
+                        }
+                        self.vm.external_set_reg(0, address);
+                    }
+                }
+
                 //*************************//
                 //**   Comap operators   **//
                 //*************************//
@@ -372,6 +553,19 @@ impl NarmHypervisor {
                     }
                 }
 
+                //SVC 0x93: origin_long() -> address: stack NeutronLongAddress
+                //Get the long form of the address that caused the current chain of executions and push to stack
+                0x93 => {
+                    let bytes = codata.current_context().origin_long_address();
+
+                    match codata.push_output_stack(&bytes) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            return Ok(HypervisorState::Error(e));
+                        }
+                    }
+                }
+
                 //SVC 0x94: sender() -> address: stack NeutronAddress
                 //Get the address that caused the current execution and push to stack
                 0x94 => {
@@ -388,6 +582,19 @@ impl NarmHypervisor {
                     }
                 }
 
+                //SVC 0x95: sender_long() -> address: stack NeutronLongAddress
+                //Get the long form of the address that caused the current execution and push to stack
+                0x95 => {
+                    let bytes = codata.current_context().sender_long_address();
+
+                    match codata.push_output_stack(&bytes) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            return Ok(HypervisorState::Error(e));
+                        }
+                    }
+                }
+
                 //SVC 0x96: execution_type() -> type: u32
                 //Get execution type and return
                 0x96 => {
@@ -396,6 +603,38 @@ impl NarmHypervisor {
                     self.vm.external_set_reg(0, execution_type);
                 }
 
+                //SVC 0x97: gas_limit() -> limit: u64
+                //Get the total amount of gas allowed to be consumed by the current execution and return
+                0x97 => {
+                    let limit = codata.current_context().gas_limit;
+
+                    self.vm.external_set_reg(0, (limit & 0x0000_0000_FFFF_FFFF) as u32);
+                    self.vm.external_set_reg(1, ((limit & 0xFFFF_FFFF_0000_0000) >> 32) as u32);
+                }
+
+                //SVC 0x98: value_sent() -> value: u64
+                //Get the number of coins sent with the current execution and return
+                0x98 => {
+                    let value = codata.current_context().value_sent;
+
+                    self.vm.external_set_reg(0, (value & 0x0000_0000_FFFF_FFFF) as u32);
+                    self.vm.external_set_reg(1, ((value & 0xFFFF_FFFF_0000_0000) >> 32) as u32);
+                }
+
+                //SVC 0x99: nest_level() -> level: u32
+                //Get the number of executions in the current chain of executions, 1 being a top level execution
+                0x99 => {
+                    self.vm.external_set_reg(0, codata.context_count() as u32);
+                }
+
+                //SVC 0x9A: permissions() -> flags: u32
+                //Get the permissions of the current execution, see ContextPermissions::to_flags for the encoding
+                0x9A => {
+                    let flags = codata.current_context().permissions.to_flags();
+
+                    self.vm.external_set_reg(0, flags);
+                }
+
                 //************************//
                 //**   Misc operators   **//
                 //************************//
@@ -429,6 +668,94 @@ impl NarmHypervisor {
             }
         }
     }
+
+    fn wrapped_enter_state(&mut self, codata: &mut CoData, callsystem: &CallSystem) -> Result<(), NeutronError> {
+        let execution_type = codata.peek_context(0)?.execution_type;
+        if execution_type == ExecutionType::Deploy && !codata.permissions().access_self {
+            return Err(NeutronError::Recoverable(RecoverableError::PureCallOfImpureContract));
+        }
+        if execution_type == ExecutionType::Deploy {
+            codata.permissions().assert_has_self_modification()?;
+        }
+        let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+        let flags = match execution_type {
+            ExecutionType::Call => load_contract_flags(&mut **storage, codata)?,
+            _ => decode_contract_flags(codata.peek_input_key("!.f".as_bytes()).ok()),
+        };
+        //the code of an upgradeable contract can change, so it can not be trusted to be pure
+        if flags & CONTRACT_FLAG_UPGRADEABLE != 0 && !codata.permissions().access_self {
+            return Err(NeutronError::Recoverable(RecoverableError::PureCallOfImpureContract));
+        }
+        let code = match execution_type {
+            ExecutionType::Call => {
+                //bytecode is excluded from self access permissions
+                codata.with_ignored_permissions(|codata| storage.private_load_state(codata, &[0x02, 0]))?
+            }
+            _ => codata.peek_input_key("!.c".as_bytes())?,
+        };
+        let data = match execution_type {
+            ExecutionType::Call => codata.with_ignored_permissions(|codata| storage.private_load_state(codata, &[0x02, 0x10]))?,
+            _ => codata.peek_input_key("!.d".as_bytes())?,
+        };
+        let entry = match execution_type {
+            ExecutionType::Call => {
+                let v = codata.with_ignored_permissions(|codata| storage.private_load_state(codata, &CONTRACT_ENTRY_KEY));
+                //contracts deployed before entry points existed begin at the start of their code
+                decode_contract_entry(v.ok())
+            }
+            _ => decode_contract_entry(codata.peek_input_key("!.e".as_bytes()).ok()),
+        };
+        //validated before any memory is mapped and charged for, so that oversized code fails as too large rather than out of gas
+        if execution_type == ExecutionType::Deploy {
+            validate_deployment(codata, &code, &data, entry)?;
+        }
+        codata.allocate_read_only_memory(code.len() as u32)?;
+        self.read_only_memory += code.len() as u32;
+        self.vm.memory.add_memory(CODE_ADDRESS, code.len() as u32).unwrap();
+        match self.vm.copy_into_memory(CODE_ADDRESS, &code) {
+            Err(_) => {
+                return Err(NeutronError::Unrecoverable(UnrecoverableError::ErrorInitializingVM));
+            }
+            _ => {}
+        }
+        codata.allocate_writeable_memory(data.len() as u32)?;
+        self.writeable_memory += data.len() as u32;
+        //contracts without .data or .bss have no data to map
+        if !data.is_empty() {
+            self.vm.memory.add_memory(DATA_ADDRESS, data.len() as u32).unwrap();
+            match self.vm.copy_into_memory(DATA_ADDRESS, &data) {
+                Err(_) => {
+                    return Err(NeutronError::Unrecoverable(UnrecoverableError::ErrorInitializingVM));
+                }
+                _ => {}
+            }
+        }
+
+        match execution_type {
+            ExecutionType::Deploy => {
+                storage.private_store_state(codata, &[0x02, 0x00], &code)?;
+                storage.private_store_state(codata, &[0x02, 0x10], &data)?;
+                storage.private_store_state(codata, &CONTRACT_FLAGS_KEY, &flags.to_le_bytes())?;
+                storage.private_store_state(codata, &CONTRACT_ENTRY_KEY, &entry.to_le_bytes())?;
+            }
+            _ => {}
+        };
+        codata.allocate_writeable_memory(STACK_SIZE)?;
+        self.writeable_memory += STACK_SIZE;
+        self.vm.memory.add_memory(STACK_ADDRESS, STACK_SIZE).unwrap();
+
+        //do init stuff
+        self.vm.set_thumb_pc_address(entry & !1);
+        Ok(())
+    }
+
+    /// Releases the memory accounted for in the CoData
+    fn release_memory(&mut self, codata: &mut CoData) {
+        codata.free_writeable_memory(self.writeable_memory);
+        codata.free_read_only_memory(self.read_only_memory);
+        self.writeable_memory = 0;
+        self.read_only_memory = 0;
+    }
 }
 
 impl VMHypervisor for NarmHypervisor {
@@ -448,9 +775,11 @@ impl VMHypervisor for NarmHypervisor {
                     }
                 };
             }
-            Err(e) => {
-                dbg!(&e);
-                println!("{}", self.vm.get_diagnostics_message());
+            Err(_) => {
+                self.fault_diagnostics = Some(self.vm.get_diagnostics_message());
+                if codata.gas_remaining == 0 {
+                    return Err(NeutronError::Unrecoverable(UnrecoverableError::OutOfGas));
+                }
                 return Err(NeutronError::Recoverable(RecoverableError::ContractExecutionError));
                 //TODO, decode into useful info
             }
@@ -463,66 +792,35 @@ impl VMHypervisor for NarmHypervisor {
     fn set_error(&mut self, code: u64) {
         self.error = Some(code);
     }
+    fn diagnostics(&self) -> Option<String> {
+        self.fault_diagnostics.clone()
+    }
+    /// Replaces the code (and data, if "!.d" is given) of the current contract. The entry point is replaced with "!.e", or with the
+    /// start of the new code if it is not given
+    fn upgrade_code(&mut self, codata: &mut CoData, storage: &mut dyn GlobalState) -> Result<(), NeutronError> {
+        let code = codata.peek_input_key("!.c".as_bytes())?;
+        let data = codata.peek_input_key("!.d".as_bytes()).ok();
+        let entry = decode_contract_entry(codata.peek_input_key("!.e".as_bytes()).ok());
+        //the new code is checked the same as at deployment. Data which is kept was already checked then
+        validate_deployment(codata, &code, data.as_deref().unwrap_or(&[]), entry)?;
+        storage.private_store_state(codata, &[0x02, 0], &code)?;
+        if let Some(data) = data {
+            storage.private_store_state(codata, &[0x02, 0x10], &data)?;
+        }
+        storage.private_store_state(codata, &CONTRACT_ENTRY_KEY, &entry.to_le_bytes())
+    }
     /// Creates the initial state, including potentially storing state to the database, decoding of bytecode, etc
     fn enter_state(&mut self, codata: &mut CoData, callsystem: &CallSystem) -> Result<(), NeutronError> {
-        let execution_type = codata.peek_context(0)?.execution_type;
-        if execution_type == ExecutionType::Deploy && !codata.permissions().access_self {
-            return Err(NeutronError::Recoverable(RecoverableError::PureCallOfImpureContract));
+        let result = self.wrapped_enter_state(codata, callsystem);
+        //exit_state is not called when entering fails, so memory must be released here
+        if result.is_err() {
+            self.release_memory(codata);
         }
-        if execution_type == ExecutionType::Deploy {
-            codata.permissions().assert_has_self_modification()?;
-        }
-        //TODO check flags for "can contract be upgraded" and if so and a pure call then return PureCallOfImpureContract
-        let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
-        let code = match execution_type {
-            ExecutionType::Call => storage.private_load_state(codata, &[0x02, 0])?,
-            _ => codata.peek_input_key("!.c".as_bytes())?,
-        };
-        self.vm.memory.add_memory(0x1_0000, code.len() as u32).unwrap();
-        match self.vm.copy_into_memory(0x1_0000, &code) {
-            Err(_) => {
-                return Err(NeutronError::Unrecoverable(UnrecoverableError::ErrorInitializingVM));
-            }
-            _ => {}
-        }
-        let data = match execution_type {
-            ExecutionType::Call => {
-                codata.ignore_permissions = true;
-                let v = storage.private_load_state(codata, &[0x02, 0x10]);
-                codata.ignore_permissions = false;
-                v?
-            }
-            _ => codata.peek_input_key("!.d".as_bytes())?,
-        };
-        self.vm.memory.add_memory(0x8001_0000, data.len() as u32).unwrap();
-        match self.vm.copy_into_memory(0x8001_0000, &data) {
-            Err(_) => {
-                return Err(NeutronError::Unrecoverable(UnrecoverableError::ErrorInitializingVM));
-            }
-            _ => {}
-        }
-
-        match execution_type {
-            ExecutionType::Deploy => {
-                storage.private_store_state(codata, &[0x02, 0x00], &code)?;
-                storage.private_store_state(codata, &[0x02, 0x10], &data)?;
-            }
-            _ => {}
-        };
-        self.vm.memory.add_memory(0x8100_0000, 0xFFFF).unwrap();
-
-        //do init stuff
-        self.vm.set_thumb_pc_address(0x1_0000);
-        Ok(())
+        result
     }
-    /// Called when exiting the VM, should commit state etc
-    fn exit_state(&mut self, codata: &mut CoData, callsystem: &CallSystem) -> Result<(), NeutronError> {
-        let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
-        if self.errored {
-            storage.revert_checkpoint(codata)?;
-        } else {
-            storage.commit_checkpoint(codata)?;
-        }
+    /// Called when exiting the VM. State checkpoints are committed or reverted by the Manager
+    fn exit_state(&mut self, codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+        self.release_memory(codata);
         Ok(())
     }
 }
@@ -559,10 +857,186 @@ open questions. Who owns hypervisors?
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::MemoryGlobalState;
+    use std::cell::RefCell;
     #[test]
     fn test_adding_vm() {
         let f = || -> Box<dyn VMHypervisor> { Box::from(NarmHypervisor::default()) };
         let mut vmm = VMManager::default();
         vmm.vm_builders.insert(2, f);
     }
+
+    fn enter_pure_call(flags: u32) -> Result<(), NeutronError> {
+        let mut codata = CoData::new();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut context = ExecutionContext::default();
+        context.self_address.version = 2;
+        context.execution_type = ExecutionType::Call;
+        context.permissions = ContextPermissions::pure_call();
+        db.write_key(&context.self_address, &[0x02, 0], &[0, 0]).unwrap();
+        db.write_key(&context.self_address, &[0x02, 0x10], &[0]).unwrap();
+        db.write_key(&context.self_address, &CONTRACT_FLAGS_KEY, &flags.to_le_bytes())
+            .unwrap();
+        codata.push_context(context).unwrap();
+
+        let mut callsystem = CallSystem::default();
+        callsystem.global_storage = Some(RefCell::new(&mut db));
+        let mut hv = NarmHypervisor::default();
+        hv.enter_state(&mut codata, &callsystem)
+    }
+
+    /// Pushes a bare execution context of the given Thumb code, with 4 bytes of data
+    fn push_bare_execution(code: &[u8], codata: &mut CoData) {
+        codata.gas_remaining = 10000;
+        codata.push_output_key("!.c".as_bytes(), code).unwrap();
+        codata.push_output_key("!.d".as_bytes(), &[0; 4]).unwrap();
+        let mut context = ExecutionContext::default();
+        context.self_address.version = 2;
+        context.execution_type = ExecutionType::BareExecution;
+        codata.push_context(context).unwrap();
+    }
+
+    /// Executes the given Thumb code as a bare execution
+    fn execute_code(code: &[u8], codata: &mut CoData) -> Result<VMResult, NeutronError> {
+        push_bare_execution(code, codata);
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut callsystem = CallSystem::default();
+        callsystem.global_storage = Some(RefCell::new(&mut db));
+        let mut hv = NarmHypervisor::default();
+        hv.enter_state(codata, &callsystem)?;
+        let result = hv.execute(codata);
+        hv.exit_state(codata, &callsystem)?;
+        result
+    }
+
+    #[test]
+    fn test_alloc_memory() {
+        let mut codata = CoData::new();
+        let code = [
+            0x10, 0x20, //movs r0, #16
+            0x80, 0xDF, //svc 0x80
+            0x64, 0x20, //movs r0, #100
+            0x80, 0xDF, //svc 0x80
+            0x01, 0x60, //str r1, [r0]
+            0xFF, 0xDF, //svc 0xFF
+        ];
+        //the status is the address of the second block, without its top bit
+        let result = execute_code(&code, &mut codata).unwrap();
+        assert_eq!(result, VMResult::Ended((HEAP_ADDRESS + 16) & !0x8000_0000));
+        //memory is released when exiting
+        assert_eq!(codata.vm_writeable_memory, 0);
+
+        //entering is free, but the second block pushes the quadratic cost of memory past the gas limit
+        let mut codata = CoData::new();
+        let entered_memory = code.len() as u64 + 4 + STACK_SIZE as u64;
+        codata
+            .gas_schedule
+            .vm_costs
+            .insert(VM_COST_MEMORY_QUADRATIC_THRESHOLD, entered_memory);
+        codata.gas_schedule.vm_costs.insert(VM_COST_MEMORY_QUADRATIC_DIVISOR, 1);
+        let result = execute_code(&code, &mut codata);
+        assert_eq!(result, Err(NeutronError::Unrecoverable(UnrecoverableError::OutOfGas)));
+        assert_eq!(codata.vm_writeable_memory, 0);
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let code = [
+            0x01, 0x20, //movs r0, #1
+            0xFF, 0xDF, //svc 0xFF
+            0x02, 0x20, //movs r0, #2
+            0xFF, 0xDF, //svc 0xFF
+        ];
+        //without an entry point execution begins at the start of the code
+        let mut codata = CoData::new();
+        assert_eq!(execute_code(&code, &mut codata).unwrap(), VMResult::Ended(1));
+        let mut codata = CoData::new();
+        codata
+            .push_output_key("!.e".as_bytes(), &(CODE_ADDRESS + 4 + 1).to_le_bytes())
+            .unwrap();
+        assert_eq!(execute_code(&code, &mut codata).unwrap(), VMResult::Ended(2));
+    }
+
+    #[test]
+    fn test_enter_state_charges_memory() {
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        let mut callsystem = CallSystem::default();
+        callsystem.global_storage = Some(RefCell::new(&mut db));
+
+        let mut codata = CoData::new();
+        codata.gas_schedule.vm_costs.insert(VM_COST_READ_ONLY_MEMORY_BYTE, 5);
+        codata.gas_schedule.vm_costs.insert(VM_COST_WRITEABLE_MEMORY_BYTE, 0);
+        push_bare_execution(&[0xFF, 0xDF], &mut codata);
+        let mut hv = NarmHypervisor::default();
+        hv.enter_state(&mut codata, &callsystem).unwrap();
+        assert_eq!(codata.gas_remaining, 10000 - 2 * 5);
+        assert_eq!(codata.vm_read_only_memory, 2);
+        assert_eq!(codata.vm_writeable_memory, 4 + STACK_SIZE);
+        hv.exit_state(&mut codata, &callsystem).unwrap();
+        assert_eq!(codata.vm_read_only_memory, 0);
+        assert_eq!(codata.vm_writeable_memory, 0);
+
+        //memory accounted before failing to enter is released
+        let mut codata = CoData::new();
+        codata.gas_schedule.vm_costs.insert(VM_COST_WRITEABLE_MEMORY_BYTE, 1);
+        push_bare_execution(&[0xFF, 0xDF], &mut codata);
+        let mut hv = NarmHypervisor::default();
+        assert_eq!(
+            hv.enter_state(&mut codata, &callsystem),
+            Err(NeutronError::Unrecoverable(UnrecoverableError::OutOfGas))
+        );
+        assert_eq!(codata.vm_read_only_memory, 0);
+        assert_eq!(codata.vm_writeable_memory, 0);
+    }
+
+    /// Enters a deployment of the given Thumb code, returning the code stored for the contract
+    fn enter_deploy(code: &[u8]) -> (Result<(), NeutronError>, Option<Vec<u8>>) {
+        let mut codata = CoData::new();
+        let mut db = MemoryGlobalState::default();
+        db.checkpoint().unwrap();
+        codata.gas_remaining = 10000;
+        codata.push_output_key("!.c".as_bytes(), code).unwrap();
+        codata.push_output_key("!.d".as_bytes(), &[0; 4]).unwrap();
+        let mut context = ExecutionContext::default();
+        context.self_address.version = 2;
+        context.execution_type = ExecutionType::Deploy;
+        context.permissions = ContextPermissions::mutable_call();
+        let address = context.self_address.clone();
+        codata.push_context(context).unwrap();
+
+        let result = {
+            let mut callsystem = CallSystem::default();
+            callsystem.global_storage = Some(RefCell::new(&mut db));
+            let mut hv = NarmHypervisor::default();
+            hv.enter_state(&mut codata, &callsystem)
+        };
+        (result, db.read_key(&address, &[0x02, 0]).ok())
+    }
+
+    #[test]
+    fn test_deploy_validates_code() {
+        let (result, stored) = enter_deploy(&[0xFF, 0xDF]);
+        assert_eq!(result, Ok(()));
+        assert_eq!(stored, Some(vec![0xFF, 0xDF]));
+        //cpsid i
+        let (result, stored) = enter_deploy(&[0x72, 0xB6, 0xFF, 0xDF]);
+        assert_eq!(result, Err(NeutronError::Host(HostError::UnsupportedInstruction)));
+        assert_eq!(stored, None);
+        //the size is checked before the memory for the code is charged for
+        let (result, stored) = enter_deploy(&vec![0; MAX_CODE_SIZE + 2]);
+        assert_eq!(result, Err(NeutronError::Host(HostError::ContractCodeTooLarge)));
+        assert_eq!(stored, None);
+    }
+
+    #[test]
+    fn test_pure_call_of_upgradeable_contract() {
+        assert!(enter_pure_call(0).is_ok());
+        assert_eq!(
+            enter_pure_call(CONTRACT_FLAG_UPGRADEABLE),
+            Err(NeutronError::Recoverable(RecoverableError::PureCallOfImpureContract))
+        );
+    }
 }
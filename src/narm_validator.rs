@@ -0,0 +1,216 @@
+//! Validation of NARM smart contracts at deployment
+//!
+//! Deployed code and data are written into protected storage and executed by every later call of the contract, so they are checked
+//! once up front rather than faulting on each call. Besides enforcing size limits and that the entry point is within the code, the
+//! code reachable from the entry point is walked, following branches, and rejected if it contains Thumb instructions which are
+//! privileged or not supported by NARM. Constant pools and other data placed between functions are never reached by the walk.
+//! Targets of indirect branches (BX, POP {pc} and so on) can not be known statically, so code only reached through them is not checked
+
+use crate::codata::*;
+use crate::narm_hypervisor::*;
+use crate::neutronerror::NeutronError::*;
+use crate::neutronerror::*;
+use neutron_common::*;
+
+/// The maximum size of the code of a contract
+pub const MAX_CODE_SIZE: usize = 0x4_0000;
+/// The maximum size of the data of a contract, including zero initialized memory
+pub const MAX_DATA_SIZE: usize = 0x4_0000;
+
+/// How execution continues after an instruction
+enum Flow {
+    /// Execution continues with the next instruction, of the given size
+    Next(usize),
+    /// Execution continues at the target offset, and also with the next instruction if the branch is conditional or returns
+    Branch { target: i64, next: Option<usize> },
+    /// Execution does not continue past the instruction, or continues at an address which is not known statically
+    End,
+}
+
+fn unsupported() -> NeutronError {
+    Host(HostError::UnsupportedInstruction)
+}
+
+/// Decodes the instruction at the given offset of the code, checking that it is supported
+fn decode(code: &[u8], offset: usize) -> Result<Flow, NeutronError> {
+    let hw = u16::from_le_bytes([code[offset], code[offset + 1]]);
+    if hw >> 11 >= 0b11101 {
+        let hw2 = match code.get(offset + 2..offset + 4) {
+            Some(b) => u16::from_le_bytes([b[0], b[1]]),
+            None => return Err(Host(HostError::InvalidContractBytecode)),
+        };
+        //BL is the only supported 32 bit instruction. MSR, MRS, barriers and Thumb-2 instructions are rejected
+        if hw >> 11 != 0b11110 || hw2 & 0xD000 != 0xD000 {
+            return Err(unsupported());
+        }
+        let s = ((hw >> 10) & 1) as u32;
+        let i1 = !(((hw2 >> 13) & 1) as u32 ^ s) & 1;
+        let i2 = !(((hw2 >> 11) & 1) as u32 ^ s) & 1;
+        let imm = (s << 24) | (i1 << 23) | (i2 << 22) | (((hw & 0x3FF) as u32) << 12) | (((hw2 & 0x7FF) as u32) << 1);
+        //sign extend the 25 bit offset
+        let imm = ((imm << 7) as i32 >> 7) as i64;
+        return Ok(Flow::Branch {
+            target: offset as i64 + 4 + imm,
+            next: Some(4),
+        });
+    }
+    Ok(match hw {
+        //UDF traps deterministically, and is used by compilers for panics and unreachable code
+        0xDE00..=0xDEFF => Flow::End,
+        //SVC 0xFE and 0xFF end the execution, other SVCs return
+        0xDFFE..=0xDFFF => Flow::End,
+        0xDF00..=0xDFFD => Flow::Next(2),
+        //conditional B
+        0xD000..=0xDDFF => Flow::Branch {
+            target: offset as i64 + 4 + (hw as u8 as i8) as i64 * 2,
+            next: Some(2),
+        },
+        //B
+        0xE000..=0xE7FF => Flow::Branch {
+            target: offset as i64 + 4 + (((hw << 5) as i16) >> 5) as i64 * 2,
+            next: None,
+        },
+        //BLX returns, BX does not
+        0x4700..=0x47FF if hw & 0x80 != 0 => Flow::Next(2),
+        0x4700..=0x47FF => Flow::End,
+        //ADD pc, Rm and MOV pc, Rm
+        _ if hw & 0xFF87 == 0x4487 || hw & 0xFF87 == 0x4687 => Flow::End,
+        //POP {.., pc}
+        0xBD00..=0xBDFF => Flow::End,
+        //REV, REV16 and REVSH, with an unallocated encoding between them
+        0xBA00..=0xBAFF if hw & 0xC0 == 0x80 => return Err(unsupported()),
+        //ADD and SUB SP, sign and zero extension, PUSH, POP and NOP
+        0xB000..=0xB0FF | 0xB200..=0xB2FF | 0xB400..=0xB5FF | 0xBA00..=0xBAFF | 0xBC00..=0xBCFF | 0xBF00 => Flow::Next(2),
+        //CPS, BKPT, hints such as WFI, and the Thumb-2 CBZ, CBNZ and IT
+        0xB000..=0xBFFF => return Err(unsupported()),
+        _ => Flow::Next(2),
+    })
+}
+
+/// Checks that the code reachable from the entry point contains only supported instructions, and that branches stay within the code
+pub fn validate_thumb_code(code: &[u8], entry: u32) -> Result<(), NeutronError> {
+    let entry = entry & !1;
+    if entry < CODE_ADDRESS || (entry - CODE_ADDRESS) as usize + 2 > code.len() {
+        return Err(Host(HostError::InvalidContractEntryPoint));
+    }
+    let mut visited = vec![false; code.len() / 2];
+    let mut pending = vec![(entry - CODE_ADDRESS) as usize];
+    while let Some(mut offset) = pending.pop() {
+        //execution running past the end of the code faults, as it would at the end of any other mapped memory
+        while offset + 2 <= code.len() && !visited[offset / 2] {
+            visited[offset / 2] = true;
+            match decode(code, offset)? {
+                Flow::Next(size) => offset += size,
+                Flow::Branch { target, next } => {
+                    if target < 0 || target as usize + 2 > code.len() {
+                        return Err(Host(HostError::InvalidContractBytecode));
+                    }
+                    pending.push(target as usize);
+                    match next {
+                        Some(size) => offset += size,
+                        None => break,
+                    }
+                }
+                Flow::End => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Validates the code, data and entry point of a contract being deployed, charging deploy gas for each byte of code and data
+pub fn validate_deployment(codata: &mut CoData, code: &[u8], data: &[u8], entry: u32) -> Result<(), NeutronError> {
+    if code.len() > MAX_CODE_SIZE {
+        return Err(Host(HostError::ContractCodeTooLarge));
+    }
+    if data.len() > MAX_DATA_SIZE {
+        return Err(Host(HostError::ContractDataTooLarge));
+    }
+    //charged before walking the code, so that the work of validating is paid for
+    let cost = codata
+        .gas_schedule
+        .vm_cost(VM_COST_DEPLOY_BYTE)
+        .saturating_mul((code.len() + data.len()) as u64);
+    codata.charge_gas(cost)?;
+    validate_thumb_code(code, entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(code: &[u8]) -> Result<(), NeutronError> {
+        validate_thumb_code(code, CODE_ADDRESS | 1)
+    }
+
+    fn assert_unsupported(instruction: &[u8]) {
+        let mut code = instruction.to_vec();
+        code.extend_from_slice(&[0xFF, 0xDF]); //svc 0xFF
+        assert_eq!(validate(&code), Err(unsupported()), "{:x?}", instruction);
+    }
+
+    #[test]
+    fn test_valid_code() {
+        let code = [
+            0x01, 0x20, //movs r0, #1
+            0x00, 0xF0, 0x02, 0xF8, //bl +4
+            0xFF, 0xDF, //svc 0xFF
+            0xFF, 0xFF, //constant pool
+            0x00, 0x28, //cmp r0, #0
+            0x00, 0xD0, //beq +0
+            0x70, 0x47, //bx lr
+            0xFE, 0xDE, //udf #254
+            0xFF, 0xFF, //constant pool
+        ];
+        assert_eq!(validate(&code), Ok(()));
+        //the constant pool is only rejected when it is reached
+        assert_eq!(validate_thumb_code(&code, CODE_ADDRESS + 8), Err(unsupported()));
+    }
+
+    #[test]
+    fn test_unsupported_instructions() {
+        assert_unsupported(&[0x72, 0xB6]); //cpsid i
+        assert_unsupported(&[0x00, 0xBE]); //bkpt
+        assert_unsupported(&[0x30, 0xBF]); //wfi
+        assert_unsupported(&[0x08, 0xBF]); //it eq
+        assert_unsupported(&[0x00, 0xB1]); //cbz r0, +0
+        assert_unsupported(&[0x80, 0xBA]); //unallocated
+        assert_unsupported(&[0x80, 0xF3, 0x08, 0x88]); //msr msp, r0
+        assert_unsupported(&[0xEF, 0xF3, 0x08, 0x80]); //mrs r0, msp
+        assert_unsupported(&[0x01, 0xEB, 0x02, 0x00]); //add.w r0, r1, r2
+    }
+
+    #[test]
+    fn test_invalid_control_flow() {
+        let invalid = Err(Host(HostError::InvalidContractBytecode));
+        assert_eq!(validate(&[0x00, 0xE0]), invalid); //b +0, past the end
+        assert_eq!(validate(&[0xFC, 0xE7]), invalid); //b -8, before the start
+        assert_eq!(validate(&[0x00, 0xF0]), invalid); //truncated bl
+        let invalid_entry = Err(Host(HostError::InvalidContractEntryPoint));
+        assert_eq!(validate_thumb_code(&[0xFF, 0xDF], CODE_ADDRESS + 2), invalid_entry);
+        assert_eq!(validate_thumb_code(&[0xFF, 0xDF], 0), invalid_entry);
+    }
+
+    #[test]
+    fn test_validate_deployment() {
+        let mut codata = CoData::new();
+        codata.gas_remaining = 100;
+        codata.gas_schedule.vm_costs.insert(VM_COST_DEPLOY_BYTE, 10);
+        assert_eq!(validate_deployment(&mut codata, &[0xFF, 0xDF], &[0; 4], CODE_ADDRESS), Ok(()));
+        assert_eq!(codata.gas_remaining, 100 - 6 * 10);
+        assert_eq!(
+            validate_deployment(&mut codata, &[0xFF, 0xDF], &[0; 4], CODE_ADDRESS),
+            Err(Unrecoverable(UnrecoverableError::OutOfGas))
+        );
+
+        let mut codata = CoData::new();
+        assert_eq!(
+            validate_deployment(&mut codata, &vec![0; MAX_CODE_SIZE + 2], &[], CODE_ADDRESS),
+            Err(Host(HostError::ContractCodeTooLarge))
+        );
+        assert_eq!(
+            validate_deployment(&mut codata, &[0xFF, 0xDF], &vec![0; MAX_DATA_SIZE + 1], CODE_ADDRESS),
+            Err(Host(HostError::ContractDataTooLarge))
+        );
+    }
+}
@@ -3,6 +3,36 @@ use std::error;
 use neutron_common::RecoverableError;
 
 
+/// Errors which a smart contract can detect and handle the same as a RecoverableError, but which are only raised by the host and so
+/// are not defined by neutron-common. They are given to the VM as error codes from 0x1000, apart from any neutron-common code
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HostError{
+    /// A nested execution ran out of gas. Only the nested execution fails, the caller continues with its own remaining gas
+    OutOfGas = 0x1000,
+    /// A call would nest more contexts than the Manager's max_call_depth allows
+    MaximumCallDepthExceeded = 0x1001,
+    /// A call would reenter a contract which is already executing, and the reentrancy policy does not allow it
+    ReentrancyNotAllowed = 0x1002,
+    /// A contract is being deployed to an address which already holds a contract
+    ContractAddressInUse = 0x1003,
+    /// The contract was not deployed as upgradeable
+    ContractNotUpgradeable = 0x1004,
+    /// An event was emitted with more than MAX_EVENT_TOPICS topics
+    TooManyEventTopics = 0x1005,
+    /// Pushing to the output costack would exceed the maximum costack size
+    CostackFull = 0x1006,
+    /// The code of a contract being deployed exceeds MAX_CODE_SIZE
+    ContractCodeTooLarge = 0x1007,
+    /// The data of a contract being deployed exceeds MAX_DATA_SIZE
+    ContractDataTooLarge = 0x1008,
+    /// The entry point of a contract being deployed is not within its code
+    InvalidContractEntryPoint = 0x1009,
+    /// The code of a contract being deployed branches outside of itself or ends with a truncated instruction
+    InvalidContractBytecode = 0x100A,
+    /// The code of a contract being deployed contains a privileged or unsupported instruction
+    UnsupportedInstruction = 0x100B
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum UnrecoverableError{
     NotImplemented,
@@ -14,6 +44,8 @@ pub enum UnrecoverableError{
     OutOfGas,
     TopLevelError(RecoverableError),
     InvalidElementOperation,
+    /// A transaction can not be included in a block as its gas limit exceeds the gas remaining in the block
+    BlockGasLimitExceeded,
     DeveloperError //used for things that should only happen by Neutron developer error
 }
 
@@ -26,6 +58,8 @@ pub enum NeutronError{
 	/// An error has occured, but if the VM implements an error handling system, it is appropriate to allow this error
     /// to be handled by the smart contract and for execution to continue
 	Recoverable(RecoverableError),
+    /// A recoverable error which is raised only by the host. This is handled the same as a Recoverable error
+    Host(HostError),
     /// An error has occured and the VM should immediately terminate, not allowing the smart contract to detect or handle this error in any capacity
     Unrecoverable(UnrecoverableError)
 }
@@ -36,6 +70,9 @@ impl fmt::Display for NeutronError {
             NeutronError::Recoverable(e) => {
                 write!(f, "Recoverable Failure! {:?}", e)
             },
+            NeutronError::Host(e) => {
+                write!(f, "Recoverable Failure! {:?}", e)
+            },
             NeutronError::Unrecoverable(e) => {
                 write!(f, "Unrecoverable Failure! {:?}", e)
             }
@@ -43,6 +80,17 @@ impl fmt::Display for NeutronError {
     }
 }
 
+impl NeutronError{
+    /// Gets the error code given to the VM for a recoverable error. Unrecoverable errors are not given to the VM
+    pub fn recoverable_code(&self) -> Option<u32>{
+        match self{
+            NeutronError::Recoverable(e) => Some(e.clone() as u32),
+            NeutronError::Host(e) => Some(*e as u32),
+            NeutronError::Unrecoverable(_) => None
+        }
+    }
+}
+
 impl error::Error for NeutronError{
 }
 
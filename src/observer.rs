@@ -0,0 +1,30 @@
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::manager::*;
+use crate::neutronerror::*;
+
+/// Receives notifications from the Manager at key points of a Neutron execution
+/// This is meant for building tracers, profilers, auditors etc without modifying the Manager itself
+/// All functions have an empty default implementation, so an observer only needs to implement the events it is interested in
+pub trait ExecutionObserver {
+    /// Called when execution of the top context of the CoData is about to begin
+    fn enter_context(&mut self, _codata: &CoData) {}
+    /// Called when execution of a context has finished, successfully or not
+    /// Unless an unrecoverable error occurred, the context has already been removed from the CoData
+    fn exit_context(&mut self, _codata: &CoData, _result: &Result<NeutronResult, NeutronError>) {}
+    /// Called when the VM requests an element call, before the element is entered
+    fn before_element_call(&mut self, _codata: &CoData, _element: u32, _function: u32) {}
+    /// Called after an element call has returned and the element has been exited
+    /// Note that for calls which result in a new contract execution, this is called before the new execution begins
+    fn after_element_call(&mut self, _codata: &CoData, _element: u32, _function: u32, _result: &Result<ElementResult, NeutronError>) {}
+    /// Called after a state checkpoint has been created for the current context
+    fn checkpoint_created(&mut self, _codata: &CoData) {}
+    /// Called after the state checkpoint of the current context has been committed
+    fn checkpoint_committed(&mut self, _codata: &CoData) {}
+    /// Called after the state checkpoint of the current context has been reverted
+    fn checkpoint_reverted(&mut self, _codata: &CoData) {}
+    /// Called when an error is raised by the VM, by an element call, or while starting a new execution
+    fn error(&mut self, _codata: &CoData, _error: &NeutronError) {}
+    /// Called after `error` when the VM itself failed and it could describe its state at the time of the failure
+    fn vm_fault(&mut self, _codata: &CoData, _diagnostics: &str) {}
+}
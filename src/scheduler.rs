@@ -0,0 +1,369 @@
+//! Parallel execution of batches of top level executions
+//!
+//! The permissions of a context limit the state it can touch, which determines what can safely be executed in parallel
+//! (see the notes on ContextPermissions):
+//!
+//! * Pure executions can be executed in parallel with anything
+//! * Isolated executions can be executed in parallel with other isolated executions which are not using the same address
+//! * Immutable executions can be executed in parallel with other immutable and pure executions, in between "barriers" of mutable/isolated executions
+//! * Mutable executions, deployments, bare executions and executions sending coins can never be executed in parallel
+//!
+//! The Scheduler splits a batch into waves of consecutive executions which do not conflict with each other. Every execution of a wave
+//! runs against its own overlay over the state as it was at the beginning of the wave, and the resulting writes are applied in batch order.
+//! The final state and results are therefore identical to executing the batch sequentially
+
+use crate::codata::*;
+use crate::db::*;
+use crate::element_interfaces::storage::*;
+use crate::interface::*;
+use crate::manager::*;
+use crate::neutronerror::*;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// The outcome of a single top level execution, as returned by Manager::execute
+pub type ExecutionOutcome = Result<NeutronResult, NeutronError>;
+
+/// A single top level execution to be run by the Scheduler
+#[derive(Clone, Debug, Default)]
+pub struct ScheduledExecution {
+    pub context: ExecutionContext,
+    /// Keys to place in the input comap of the execution, such as "!.c" and "!.d" for deployments
+    pub input_map: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ScheduledExecution {
+    pub fn new(context: ExecutionContext) -> ScheduledExecution {
+        ScheduledExecution {
+            context,
+            input_map: vec![],
+        }
+    }
+    /// Prepares the CoData for running this execution, by pushing its context and inputs and setting its gas
+    pub fn prepare(&self, codata: &mut CoData) -> Result<(), NeutronError> {
+        codata.gas_remaining = self.context.gas_limit;
+        codata.push_context(self.context.clone())?;
+        for (key, value) in self.input_map.iter() {
+            codata.push_input_key(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum StateAccess {
+    Pure,
+    Isolated,
+    Immutable,
+    Exclusive,
+}
+
+fn state_access(context: &ExecutionContext) -> StateAccess {
+    let permissions = context.permissions;
+    //sending coins modifies the balance of the sender, which is another address
+    if context.execution_type != ExecutionType::Call || permissions.modify_external || context.value_sent > 0 {
+        StateAccess::Exclusive
+    } else if permissions.modify_self {
+        if permissions.access_external {
+            //reading other addresses while modifying itself does not fit any of the parallel permission sets
+            StateAccess::Exclusive
+        } else {
+            StateAccess::Isolated
+        }
+    } else if permissions.access_self || permissions.access_external {
+        StateAccess::Immutable
+    } else {
+        StateAccess::Pure
+    }
+}
+
+fn conflicts(a: &ExecutionContext, b: &ExecutionContext) -> bool {
+    match (state_access(a), state_access(b)) {
+        (StateAccess::Exclusive, _) | (_, StateAccess::Exclusive) => true,
+        (StateAccess::Pure, _) | (_, StateAccess::Pure) => false,
+        (StateAccess::Isolated, StateAccess::Isolated) => a.self_address == b.self_address,
+        (StateAccess::Immutable, StateAccess::Immutable) => false,
+        _ => true,
+    }
+}
+
+/// Splits a batch into waves of consecutive executions which can be executed in parallel
+pub fn plan_waves(executions: &[ScheduledExecution]) -> Vec<Range<usize>> {
+    let mut waves = vec![];
+    let mut start = 0;
+    for i in 0..executions.len() {
+        if executions[start..i].iter().any(|e| conflicts(&e.context, &executions[i].context)) {
+            waves.push(start..i);
+            start = i;
+        }
+    }
+    if start < executions.len() {
+        waves.push(start..executions.len());
+    }
+    waves
+}
+
+/// Executes batches of top level executions on multiple worker threads
+pub struct Scheduler {
+    /// The maximum number of executions to run at the same time. With a single worker the batch is executed sequentially
+    pub workers: usize,
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler {
+            workers: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+}
+
+impl Scheduler {
+    /// Executes a batch of top level executions, returning the outcome of each execution in batch order
+    /// `execute` runs a single execution against the given state, whose writes are applied to `state` only if it returns Ok.
+    /// It is called from worker threads, and so must construct its own Manager, CallSystem and Elements for each execution
+    /// The state must have an open checkpoint, which receives the writes of the batch
+    /// Besides GlobalState, the state must implement StateSnapshot (as MemoryGlobalState does), as the executions of a wave read it at the same time
+    pub fn execute_batch<S, F>(
+        &self,
+        state: &mut S,
+        executions: &[ScheduledExecution],
+        execute: F,
+    ) -> Result<Vec<ExecutionOutcome>, NeutronError>
+    where
+        S: GlobalState + StateSnapshot + Sync,
+        F: Fn(&mut dyn GlobalState, &ScheduledExecution) -> ExecutionOutcome + Sync,
+    {
+        //writes are applied by the host rather than by an execution, so no context is needed
+        let mut codata = CoData::new();
+        let mut outcomes = Vec::with_capacity(executions.len());
+        for wave in plan_waves(executions) {
+            if self.workers <= 1 || wave.len() == 1 {
+                for execution in executions[wave].iter() {
+                    let (outcome, writes) = execute_on_overlay(&*state, execution, &execute)?;
+                    state.apply_writes(&mut codata, writes)?;
+                    outcomes.push(outcome);
+                }
+                continue;
+            }
+            for finished in self.execute_wave(&*state, &executions[wave], &execute) {
+                let (outcome, writes) = finished?;
+                state.apply_writes(&mut codata, writes)?;
+                outcomes.push(outcome);
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Executes every execution of the wave against its own overlay over `state`, returning the outcomes and writes in wave order
+    fn execute_wave<S, F>(
+        &self,
+        state: &S,
+        wave: &[ScheduledExecution],
+        execute: &F,
+    ) -> Vec<Result<(ExecutionOutcome, StateWrites), NeutronError>>
+    where
+        S: StateSnapshot + Sync,
+        F: Fn(&mut dyn GlobalState, &ScheduledExecution) -> ExecutionOutcome + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let mut finished: Vec<_> = thread::scope(|s| {
+            let workers: Vec<_> = (0..self.workers.min(wave.len()))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut finished = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= wave.len() {
+                                break;
+                            }
+                            finished.push((i, execute_on_overlay(state, &wave[i], execute)));
+                        }
+                        finished
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+        finished.sort_by_key(|(i, _)| *i);
+        finished.into_iter().map(|(_, f)| f).collect()
+    }
+}
+
+/// Runs an execution against an overlay over `state`, returning its outcome and writes. A failed execution has no writes
+fn execute_on_overlay<S, F>(state: &S, execution: &ScheduledExecution, execute: &F) -> Result<(ExecutionOutcome, StateWrites), NeutronError>
+where
+    S: StateSnapshot,
+    F: Fn(&mut dyn GlobalState, &ScheduledExecution) -> ExecutionOutcome,
+{
+    let mut overlay = OverlayGlobalState::new(state);
+    let outcome = execute(&mut overlay, execution);
+    if outcome.is_err() {
+        return Ok((outcome, StateWrites::new()));
+    }
+    Ok((outcome, overlay.take_writes()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callsystem::*;
+    use crate::element_interfaces::call_contract::*;
+    use crate::test_helpers::*;
+    use crate::vmmanager::*;
+    use neutron_common::*;
+
+    /// Increments a counter in its storage if it can modify itself, otherwise only reads it. Ends with the counter value
+    /// Each increment also sets a key of its own, so that merging several writes to the same address is covered
+    /// If input key [7] is set, a top level execution first attempts to deploy a contract, which uses up the deployment nonce if permitted
+    #[derive(Default)]
+    struct CounterVM {
+        counter: u32,
+        deployed: bool,
+    }
+    impl VMHypervisor for CounterVM {
+        fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError> {
+            if codata.context_count() == 1 && codata.peek_input_key(&[7]).is_ok() && !self.deployed {
+                self.deployed = true;
+                codata.push_output_stack(&0u64.to_le_bytes())?;
+                codata.push_output_stack(&100u64.to_le_bytes())?;
+                return Ok(VMResult::ElementCall(
+                    CALL_CONTRACT_FEATURE,
+                    CallContractFunctions::DeployContract as u32,
+                ));
+            }
+            Ok(VMResult::Ended(self.counter))
+        }
+        fn set_result(&mut self, _code: u64) {}
+        fn set_error(&mut self, _code: u64) {}
+        fn enter_state(&mut self, codata: &mut CoData, callsystem: &CallSystem) -> Result<(), NeutronError> {
+            let permissions = codata.permissions();
+            if !permissions.access_self {
+                return Ok(());
+            }
+            let mut storage = callsystem.global_storage.as_ref().unwrap().borrow_mut();
+            self.counter = storage.load_state(codata, &[1]).map(|v| v[0] as u32).unwrap_or(0);
+            if permissions.modify_self {
+                self.counter += 1;
+                storage.store_state(codata, &[1], &[self.counter as u8])?;
+                storage.store_state(codata, &[2 + self.counter as u8], &[1])?;
+            }
+            Ok(())
+        }
+        fn exit_state(&mut self, _codata: &mut CoData, _callsystem: &CallSystem) -> Result<(), NeutronError> {
+            Ok(())
+        }
+    }
+
+    fn execute_counter(state: &mut dyn GlobalState, execution: &ScheduledExecution) -> ExecutionOutcome {
+        let countervm = || -> Box<dyn VMHypervisor> { Box::from(CounterVM::default()) };
+        execute_test_vm(state, execution, countervm)
+    }
+
+    fn execution(n: u8, permissions: ContextPermissions) -> ScheduledExecution {
+        let mut context = ExecutionContext::default();
+        context.self_address = address(n);
+        context.permissions = permissions;
+        context.gas_limit = 1000;
+        ScheduledExecution::new(context)
+    }
+
+    fn batch() -> Vec<ScheduledExecution> {
+        vec![
+            execution(1, ContextPermissions::isolated_call()),
+            execution(2, ContextPermissions::isolated_call()),
+            execution(1, ContextPermissions::immutable_call()),
+            execution(2, ContextPermissions::pure_call()),
+            execution(1, ContextPermissions::isolated_call()),
+            execution(1, ContextPermissions::mutable_call()),
+            execution(2, ContextPermissions::immutable_call()),
+            execution(2, ContextPermissions::isolated_call()),
+        ]
+    }
+
+    fn deploying(n: u8, permissions: ContextPermissions) -> ScheduledExecution {
+        let mut execution = execution(n, permissions);
+        execution.input_map.push((vec![7], vec![1]));
+        execution
+    }
+
+    /// Executions attempting to deploy, of which only the mutable ones may use up the deployment nonce
+    fn deploying_batch() -> Vec<ScheduledExecution> {
+        vec![
+            deploying(1, ContextPermissions::immutable_call()),
+            execution(1, ContextPermissions::immutable_call()),
+            deploying(2, ContextPermissions::pure_call()),
+            deploying(1, ContextPermissions::mutable_call()),
+            deploying(1, ContextPermissions::immutable_call()),
+            deploying(2, ContextPermissions::isolated_call()),
+            deploying(2, ContextPermissions::mutable_call()),
+            deploying(2, ContextPermissions::immutable_call()),
+        ]
+    }
+
+    fn execute_batch(workers: usize, batch: &[ScheduledExecution]) -> (Vec<u32>, MemoryGlobalState) {
+        let mut state = MemoryGlobalState::default();
+        state.checkpoint().unwrap();
+        let outcomes = Scheduler { workers }.execute_batch(&mut state, batch, execute_counter).unwrap();
+        state.commit().unwrap();
+        (outcomes.into_iter().map(|o| o.unwrap().status).collect(), state)
+    }
+
+    #[test]
+    fn test_plan_waves() {
+        assert_eq!(plan_waves(&batch()), vec![0..2, 2..4, 4..5, 5..6, 6..7, 7..8]);
+        let pure = vec![execution(1, ContextPermissions::pure_call()); 3];
+        assert_eq!(plan_waves(&pure), vec![0..3]);
+        let mut deploy = execution(2, ContextPermissions::pure_call());
+        deploy.context.execution_type = ExecutionType::Deploy;
+        assert_eq!(plan_waves(&[pure[0].clone(), deploy, pure[0].clone()]), vec![0..1, 1..2, 2..3]);
+        let mut paying = execution(2, ContextPermissions::isolated_call());
+        paying.context.value_sent = 1;
+        assert_eq!(plan_waves(&[pure[0].clone(), paying, pure[0].clone()]), vec![0..1, 1..2, 2..3]);
+        assert!(plan_waves(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let (sequential, mut sequential_state) = execute_batch(1, &batch());
+        let (parallel, mut parallel_state) = execute_batch(4, &batch());
+        assert_eq!(sequential, vec![1, 1, 1, 0, 2, 3, 1, 2]);
+        assert_eq!(parallel, sequential);
+        for n in 1..=2 {
+            for key in 1..=6 {
+                let user_key = [95, key];
+                assert_eq!(
+                    sequential_state.read_key(&address(n), &user_key).ok(),
+                    parallel_state.read_key(&address(n), &user_key).ok()
+                );
+            }
+        }
+        assert_eq!(parallel_state.read_key(&address(1), &[95, 1]).unwrap(), vec![3]);
+        assert_eq!(parallel_state.read_key(&address(1), &[95, 3]).unwrap(), vec![1]);
+        assert_eq!(parallel_state.read_key(&address(2), &[95, 1]).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_parallel_deployments_match_sequential() {
+        let batch = deploying_batch();
+        assert_eq!(plan_waves(&batch), vec![0..3, 3..4, 4..5, 5..6, 6..7, 7..8]);
+        let (sequential, mut sequential_state) = execute_batch(1, &batch);
+        let (parallel, mut parallel_state) = execute_batch(4, &batch);
+        assert_eq!(parallel, sequential);
+        for n in 1..=2 {
+            assert_eq!(
+                sequential_state.read_key(&address(n), &DEPLOY_NONCE_KEY).ok(),
+                parallel_state.read_key(&address(n), &DEPLOY_NONCE_KEY).ok()
+            );
+            assert_eq!(
+                sequential_state.read_key(&address(n), &[95, 1]).ok(),
+                parallel_state.read_key(&address(n), &[95, 1]).ok()
+            );
+            //only the mutable executions deployed, as the isolated one can not grant its permissions to the deployed contract
+            assert_eq!(
+                parallel_state.read_key(&address(n), &DEPLOY_NONCE_KEY).unwrap(),
+                1u64.to_le_bytes().to_vec()
+            );
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Fixtures shared by the unit tests of several modules
+
+use crate::callsystem::*;
+use crate::codata::*;
+use crate::element_interfaces::call_contract::*;
+use crate::element_interfaces::storage::GlobalState;
+use crate::manager::*;
+use crate::neutronerror::*;
+use crate::scheduler::*;
+use crate::vmmanager::*;
+use crate::AddressDecoding;
+use neutron_common::NeutronAddress;
+use std::cell::RefCell;
+
+/// The VM number test VMs are registered as, which is also the version of addresses given by `address`
+pub const TEST_VM_VERSION: u32 = 1;
+
+/// Returns an address using the test VM, distinguished by `n`
+pub fn address(n: u8) -> NeutronAddress {
+    let mut address = NeutronAddress::default();
+    address.version = TEST_VM_VERSION;
+    address.data[0] = n;
+    address
+}
+
+/// Executes `execution` against `state`, with `vm` registered as the test VM and a ContractCaller available to it
+pub fn execute_test_vm(state: &mut dyn GlobalState, execution: &ScheduledExecution, vm: fn() -> Box<dyn VMHypervisor>) -> ExecutionOutcome {
+    let mut codata = CoData::new();
+    execution.prepare(&mut codata)?;
+    let mut caller = ContractCaller::default();
+    let mut callsystem = CallSystem::default();
+    callsystem.global_storage = Some(RefCell::new(state));
+    callsystem.add_call(CALL_CONTRACT_FEATURE, &mut caller).unwrap();
+    let mut vmm = VMManager::default();
+    vmm.vm_builders.insert(TEST_VM_VERSION, vm);
+    Manager::default().execute(&mut codata, &callsystem, &vmm)
+}
+
+/// Pushes the arguments of a CallContract element call of the contract at the default address of the test VM, returning the element
+/// call for the VM to make. Comap keys and costack items meant for the callee must be pushed beforehand
+pub fn call_test_contract(codata: &mut CoData, value: u64, gas_limit: u64) -> Result<VMResult, NeutronError> {
+    let mut address = NeutronAddress::default();
+    address.version = TEST_VM_VERSION;
+    codata.push_output_stack(&value.to_le_bytes())?;
+    codata.push_output_stack(&gas_limit.to_le_bytes())?;
+    codata.push_output_stack(&address.decode())?;
+    Ok(VMResult::ElementCall(
+        CALL_CONTRACT_FEATURE,
+        CallContractFunctions::CallContract as u32,
+    ))
+}
@@ -1,6 +1,5 @@
 extern crate neutron_common;
 extern crate ring;
-extern crate elf;
 /*
 use crate::db::*;
 use std::path::PathBuf;
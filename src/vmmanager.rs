@@ -1,6 +1,7 @@
 use crate::neutronerror::*;
 use crate::codata::*;
 use crate::callsystem::*;
+use crate::element_interfaces::storage::GlobalState;
 use std::collections::hash_map::*;
 
 pub trait VMHypervisor{
@@ -10,8 +11,17 @@ pub trait VMHypervisor{
     fn execute(&mut self, codata: &mut CoData) -> Result<VMResult, NeutronError>;
     fn set_result(&mut self, result: u64);
     fn set_error(&mut self, code: u64);
-    /// Called when exiting the VM, should commit state etc
+    /// Called when exiting the VM. Note that state checkpoints are committed or reverted by the Manager, not the VM
     fn exit_state(&mut self, codata: &mut CoData, callsystem: & CallSystem) -> Result<(), NeutronError>;
+    /// Describes the state of the VM after execute has failed, such as its registers. This is meant only for debugging
+    fn diagnostics(&self) -> Option<String>{
+        None
+    }
+    /// Replaces the code of the current contract for the UpgradeContract element call, using the new code given in the input comap as at deployment
+    /// The new code must be validated the same as at deployment. VMs which can not do so do not support upgrades, which is the default
+    fn upgrade_code(&mut self, _codata: &mut CoData, _storage: &mut dyn GlobalState) -> Result<(), NeutronError>{
+        Err(NeutronError::Host(HostError::ContractNotUpgradeable))
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -0,0 +1,30 @@
+//! Directly plug a `main` symbol instead of using `#[entry]`
+
+#![deny(warnings)]
+#![no_main]
+#![no_std]
+
+use neutron_star_rt::*;
+extern crate panic_halt;
+
+const DEBUG_DATA_FEATURE: u32 = 0x4000_0001;
+const CALL_CONTRACT_FEATURE: u32 = 5;
+
+#[no_mangle]
+pub unsafe extern "C" fn main() -> ! {
+    // Get the address of the contract to call from the provided input stack
+    __system_call(DEBUG_DATA_FEATURE, 1); // DebugDataFunctions::PushInputStack
+    let mut address: [u8; 24] = [0; 24];
+    let _ = __pop_costack(address.as_mut_ptr(), 24);
+
+    // Arguments are pushed in reverse order, so that the address is popped first
+    let value: u64 = 0;
+    let gas_limit: u64 = 5000;
+    __push_costack(value.to_le_bytes().as_ptr(), 8);
+    __push_costack(gas_limit.to_le_bytes().as_ptr(), 8);
+    __push_costack(address.as_ptr(), 24);
+
+    // Exit with the status code of the called contract
+    let status = __system_call(CALL_CONTRACT_FEATURE, 1); // CallContractFunctions::CallContract
+    __exit(status as u32);
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use neutron_host::element_interfaces::debug_data::*;
+use neutron_host::harness::*;
+use neutron_host::interface::*;
+
+use common::*;
+
+const CONTRACT_NAME: &'static str = "element_call_contract_caller";
+const CALLEE_NAME: &'static str = "smoke_test_deploy_call";
+
+#[test]
+// Deploy a contract, then call it from another contract and check that its status code is passed back
+fn test_call_deployed_contract() {
+    let mut harness = TestHarness::default();
+    let callee_context = ExecutionContext::create_default_random_context();
+    let callee_address = harness.next_contract_address(callee_context.sender);
+    harness.deploy_binary_using_default_callsystem(&get_contract_path(CALLEE_NAME), callee_context);
+
+    let mut debugdata = DebugDataInjector::default();
+    debugdata.inject_stack.push_address(callee_address);
+    harness.debugdata = debugdata;
+
+    let context = ExecutionContext::create_default_random_context();
+    let result = harness.execute_binary_using_default_callsystem(&get_contract_path(CONTRACT_NAME), context);
+    assert_eq!(result.status, 1);
+}